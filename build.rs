@@ -8,4 +8,12 @@ fn main() {
     &[proto_root],
     "src/service/rpc/proto",
   ).expect("Failed to compile gRPC definitions!");
+
+  let extension_root = "proto";
+  println!("cargo:rerun-if-changed={}", extension_root);
+  protoc_grpcio::compile_grpc_protos(
+    &["extension.proto", "admin.proto"],
+    &[extension_root],
+    "src/service/rpc/proto",
+  ).expect("Failed to compile gRPC extension and admin definitions!");
 }
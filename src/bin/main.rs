@@ -1,6 +1,6 @@
 use failure::{Error, ResultExt};
 use log::{error, info, LevelFilter};
-use mucs::{ConnectConfig, ConnectServer};
+use mucs::{ConnectArgs, ConnectExtensions, ConnectServer};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::{thread, time::Duration};
@@ -10,10 +10,37 @@ use structopt::StructOpt;
 #[structopt(about = "Mu Online Connect Server")]
 pub struct Config {
   #[structopt(flatten)]
-  pub connect: ConnectConfig,
+  pub connect: ConnectArgs,
+
+  #[structopt(subcommand)]
+  pub command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+pub enum Command {
+  #[structopt(
+    name = "check-config",
+    about = "Validates the configuration and exits"
+  )]
+  CheckConfig,
+}
+
+fn check_config(connect: &ConnectArgs) -> Result<(), Error> {
+  let config = connect
+    .resolve()
+    .context("Error trying to load configuration")?;
+  ConnectServer::check_config(&config, &ConnectExtensions::new())?;
+  info!("Configuration is valid");
+  Ok(())
 }
 
 fn run() -> Result<(), Error> {
+  // Parse any CLI arguments
+  let Config { connect, command } = Config::from_args();
+  if let Some(Command::CheckConfig) = command {
+    return check_config(&connect);
+  }
+
   let running = Arc::new(AtomicBool::new(true));
   let runningc = running.clone();
 
@@ -22,11 +49,22 @@ fn run() -> Result<(), Error> {
     runningc.store(false, Ordering::SeqCst);
   }).context("Error setting interrupt handler")?;
 
-  // Parse any CLI arguments
-  let Config { connect } = Config::from_args();
-  let server = ConnectServer::spawn(connect).context("Error trying to spawn connect server")?;
+  let reload = Arc::new(AtomicBool::new(false));
+  signal_hook::flag::register(signal_hook::SIGHUP, reload.clone())
+    .context("Error setting reload handler")?;
+
+  let config = connect
+    .resolve()
+    .context("Error trying to load configuration")?;
+  let server = ConnectServer::spawn(config).context("Error trying to spawn connect server")?;
 
   while server.is_active() && running.load(Ordering::SeqCst) {
+    if reload.swap(false, Ordering::SeqCst) {
+      info!("Reloading configuration...");
+      if let Err(error) = connect.resolve().and_then(|config| server.reload(config)) {
+        error!("Failed to reload configuration — {}", error);
+      }
+    }
     thread::sleep(Duration::from_millis(100));
   }
 
@@ -0,0 +1,360 @@
+use super::{ConfigLayer, ConnectConfig};
+use crate::service::{ExtensionFallback, WelcomePacket, XorKey};
+use crate::state::{MaintenanceDisplay, RealmOverflow, StaleRealmDisplay, StaticRealmPolicy};
+use crate::util::{IpNetwork, RateLimit};
+use crate::Result;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+use structopt::StructOpt;
+
+/// Command line arguments, taking precedence over any other configuration source.
+#[derive(StructOpt)]
+pub struct ConnectArgs {
+  #[structopt(
+    short = "c",
+    long = "config",
+    help = "Load the configuration from this TOML file [env: MUCS_CONFIG]",
+    parse(from_os_str)
+  )]
+  pub config: Option<PathBuf>,
+
+  #[structopt(
+    short = "h",
+    long = "host",
+    help = "Bind to this IP client address [default: 0.0.0.0]"
+  )]
+  pub host: Option<IpAddr>,
+
+  #[structopt(
+    short = "p",
+    long = "port",
+    help = "Bind to this client listener port [default: 2004]"
+  )]
+  pub port: Option<u16>,
+
+  #[structopt(
+    short = "l",
+    long = "listen",
+    help = "Additionally bind to this client address (e.g [::]:2004)",
+    raw(number_of_values = "1")
+  )]
+  pub listen: Vec<SocketAddr>,
+
+  #[structopt(
+    long = "max-idle-time",
+    help = "Maximum idle time until a client is disconnected [default: 100s]",
+    parse(try_from_str = "humantime::parse_duration")
+  )]
+  pub max_idle_time: Option<Duration>,
+
+  #[structopt(
+    long = "max-unresponsive-time",
+    help = "Maximum unresponsive time until a client is dropped [default: 60s]",
+    parse(try_from_str = "humantime::parse_duration")
+  )]
+  pub max_unresponsive_time: Option<Duration>,
+
+  #[structopt(
+    long = "max-packet-size",
+    help = "Maximum packet size allowed from a client [default: 6]"
+  )]
+  pub max_packet_size: Option<usize>,
+
+  #[structopt(
+    long = "max-requests",
+    help = "Maximum requests allowed from a client, or 0 for no limit [default: 0]"
+  )]
+  pub max_requests: Option<usize>,
+
+  #[structopt(
+    long = "request-rate",
+    help = "Maximum rate of requests from a client [default: 10/1s]"
+  )]
+  pub request_rate: Option<RateLimit>,
+
+  #[structopt(
+    long = "realm-list-rate",
+    help = "Maximum rate of realm list requests from a client [default: 5/10s]"
+  )]
+  pub realm_list_rate: Option<RateLimit>,
+
+  #[structopt(
+    long = "realm-connect-rate",
+    help = "Maximum rate of realm connect requests from a client (e.g 3/10s)"
+  )]
+  pub realm_connect_rate: Option<RateLimit>,
+
+  #[structopt(
+    long = "max-connections",
+    help = "Maximum connections the server should handle [default: 1000]"
+  )]
+  pub max_connections: Option<usize>,
+
+  #[structopt(
+    long = "max-connections-per-ip",
+    help = "Maximum connections per IP [default: 1]"
+  )]
+  pub max_connections_per_ip: Option<usize>,
+
+  #[structopt(
+    long = "connection-rate",
+    help = "Maximum rate of new connections (e.g 200/1s)"
+  )]
+  pub connection_rate: Option<RateLimit>,
+
+  #[structopt(
+    long = "connection-rate-per-ip",
+    help = "Maximum rate of new connections per IP (e.g 5/10s)"
+  )]
+  pub connection_rate_per_ip: Option<RateLimit>,
+
+  #[structopt(
+    long = "access-list",
+    help = "Refuse clients by the allow and deny lists of this TOML file",
+    parse(from_os_str)
+  )]
+  pub access_list: Option<PathBuf>,
+
+  #[structopt(
+    long = "staff",
+    help = "Grant staff access to this IP address or CIDR range (e.g 10.0.0.0/8)",
+    raw(number_of_values = "1")
+  )]
+  pub staff: Vec<IpNetwork>,
+
+  #[structopt(
+    long = "vip",
+    help = "Allow this IP address or CIDR range to exceed a realm's capacity (e.g 10.0.0.0/8)",
+    raw(number_of_values = "1")
+  )]
+  pub vip: Vec<IpNetwork>,
+
+  #[structopt(
+    long = "ipv4-prefix-len",
+    help = "Count connections per IPv4 network of this prefix length [default: 32]"
+  )]
+  pub ipv4_prefix_len: Option<u8>,
+
+  #[structopt(
+    long = "ipv6-prefix-len",
+    help = "Count connections per IPv6 network of this prefix length [default: 64]"
+  )]
+  pub ipv6_prefix_len: Option<u8>,
+
+  #[structopt(
+    long = "ignore-unknown-packets",
+    help = "Ignore unknown packets from a client instead of disconnecting [default: false]"
+  )]
+  pub ignore_unknown_packets: Option<bool>,
+
+  #[structopt(
+    long = "require-handshake",
+    help = "Require the version handshake before any other request [default: false]"
+  )]
+  pub require_handshake: Option<bool>,
+
+  #[structopt(
+    long = "welcome-packet",
+    help = "Greet accepted clients with either the stock packet or hex bytes (e.g C1 04 00 01)"
+  )]
+  pub welcome_packet: Option<WelcomePacket>,
+
+  #[structopt(
+    long = "xor-key",
+    help = "Decrypt client packets with this 32 byte hex XOR key [default: stock key]"
+  )]
+  pub xor_key: Option<XorKey>,
+
+  #[structopt(
+    long = "xor-key-file",
+    help = "Decrypt client packets with the XOR key of this file, either raw or hex",
+    parse(from_os_str)
+  )]
+  pub xor_key_file: Option<PathBuf>,
+
+  #[structopt(
+    long = "profile",
+    help = "Use this protocol profile for the primary listener [default: standard layout]"
+  )]
+  pub profile: Option<String>,
+
+  #[structopt(
+    long = "ban-threshold",
+    help = "Ban networks reaching this many client error strikes, or 0 to disable [default: 0]"
+  )]
+  pub ban_threshold: Option<u32>,
+
+  #[structopt(
+    long = "ban-duration",
+    help = "Duration of each ban [default: 1h]",
+    parse(try_from_str = "humantime::parse_duration")
+  )]
+  pub ban_duration: Option<Duration>,
+
+  #[structopt(
+    long = "ban-list",
+    help = "Persist any bans across restarts in this TOML file",
+    parse(from_os_str)
+  )]
+  pub ban_list: Option<PathBuf>,
+
+  #[structopt(
+    long = "strike-decay",
+    help = "Time until a single strike has expired [default: 1m]",
+    parse(try_from_str = "humantime::parse_duration")
+  )]
+  pub strike_decay: Option<Duration>,
+
+  #[structopt(
+    long = "extension-timeout",
+    help = "Maximum time to await a response from an extension service [default: 2s]",
+    parse(try_from_str = "humantime::parse_duration")
+  )]
+  pub extension_timeout: Option<Duration>,
+
+  #[structopt(
+    long = "extension-fallback",
+    help = "Either ignore, disconnect or hex bytes when an extension fails [default: ignore]"
+  )]
+  pub extension_fallback: Option<ExtensionFallback>,
+
+  #[structopt(
+    long = "rpc-host",
+    help = "Bind to this RPC domain [default: 0.0.0.0]"
+  )]
+  pub rpc_host: Option<String>,
+
+  #[structopt(
+    long = "rpc-port",
+    help = "Bind to this RPC listener port [default: 0]"
+  )]
+  pub rpc_port: Option<u16>,
+
+  #[structopt(
+    long = "rpc-admin",
+    help = "Serve the unauthenticated admin RPC service, changing realm states [default: false]"
+  )]
+  pub rpc_admin: Option<bool>,
+
+  #[structopt(
+    long = "static-realm-policy",
+    help = "Either reject or override RPC realms conflicting with static ones [default: reject]"
+  )]
+  pub static_realm_policy: Option<StaticRealmPolicy>,
+
+  #[structopt(
+    long = "realm-ttl",
+    help = "Mark RPC realms without status updates for this long as stale (e.g 30s)",
+    parse(try_from_str = "humantime::parse_duration")
+  )]
+  pub realm_ttl: Option<Duration>,
+
+  #[structopt(
+    long = "realm-eviction-delay",
+    help = "Evict stale realms after this long [default: 1m]",
+    parse(try_from_str = "humantime::parse_duration")
+  )]
+  pub realm_eviction_delay: Option<Duration>,
+
+  #[structopt(
+    long = "stale-realms",
+    help = "Either list stale realms as full or hide them [default: full]"
+  )]
+  pub stale_realms: Option<StaleRealmDisplay>,
+
+  #[structopt(
+    long = "maintenance-display",
+    help = "Either list realms under maintenance as full or omit them [default: full]"
+  )]
+  pub maintenance_display: Option<MaintenanceDisplay>,
+
+  #[structopt(
+    long = "realm-overflow",
+    help = "Either allow, reject or redirect clients joining a full realm [default: allow]"
+  )]
+  pub realm_overflow: Option<RealmOverflow>,
+
+  #[structopt(
+    long = "vip-overflow-margin",
+    help = "VIP clients may exceed a realm's capacity by [default: 0]"
+  )]
+  pub vip_overflow_margin: Option<usize>,
+}
+
+impl ConnectArgs {
+  /// Resolves the configuration, with the arguments taking precedence over the configuration
+  /// file and environment variables.
+  pub fn resolve(&self) -> Result<ConnectConfig> {
+    let mut config = ConnectConfig::load(self.config.as_ref().map(PathBuf::as_path))?;
+    config.apply(self.layer());
+    Ok(config)
+  }
+
+  /// Returns the configuration layer specified by the arguments.
+  fn layer(&self) -> ConfigLayer {
+    ConfigLayer {
+      host: self.host,
+      port: self.port,
+      listen: if self.listen.is_empty() {
+        None
+      } else {
+        Some(self.listen.clone())
+      },
+      max_idle_time: self.max_idle_time,
+      max_unresponsive_time: self.max_unresponsive_time,
+      max_packet_size: self.max_packet_size,
+      max_requests: self.max_requests,
+      request_rate: self.request_rate,
+      realm_list_rate: self.realm_list_rate,
+      realm_connect_rate: self.realm_connect_rate,
+      max_connections: self.max_connections,
+      max_connections_per_ip: self.max_connections_per_ip,
+      connection_rate: self.connection_rate,
+      connection_rate_per_ip: self.connection_rate_per_ip,
+      access_list: self.access_list.clone(),
+      staff: if self.staff.is_empty() {
+        None
+      } else {
+        Some(self.staff.clone())
+      },
+      vip: if self.vip.is_empty() {
+        None
+      } else {
+        Some(self.vip.clone())
+      },
+      ipv4_prefix_len: self.ipv4_prefix_len,
+      ipv6_prefix_len: self.ipv6_prefix_len,
+      ignore_unknown_packets: self.ignore_unknown_packets,
+      require_handshake: self.require_handshake,
+      welcome_packet: self.welcome_packet.clone(),
+      xor_key: self.xor_key,
+      xor_key_file: self.xor_key_file.clone(),
+      versions: None,
+      patch_server: None,
+      profile: self.profile.clone(),
+      profiles: None,
+      ban_threshold: self.ban_threshold,
+      ban_duration: self.ban_duration,
+      ban_list: self.ban_list.clone(),
+      strike_decay: self.strike_decay,
+      strike_weights: None,
+      extension_timeout: self.extension_timeout,
+      extension_fallback: self.extension_fallback.clone(),
+      scripts: None,
+      listeners: None,
+      rpc_host: self.rpc_host.clone(),
+      rpc_port: self.rpc_port,
+      rpc_admin: self.rpc_admin,
+      static_realm_policy: self.static_realm_policy,
+      realm_ttl: self.realm_ttl,
+      realm_eviction_delay: self.realm_eviction_delay,
+      stale_realms: self.stale_realms,
+      maintenance_display: self.maintenance_display,
+      realm_overflow: self.realm_overflow,
+      vip_overflow_margin: self.vip_overflow_margin,
+      realms: None,
+      realm_groups: None,
+    }
+  }
+}
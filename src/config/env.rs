@@ -0,0 +1,99 @@
+use super::ConfigLayer;
+use crate::Result;
+use failure::format_err;
+use std::env::{self, VarError};
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// The prefix used by all environment variables.
+const PREFIX: &str = "MUCS_";
+
+/// Loads a configuration layer from any `MUCS_*` environment variables.
+pub fn load() -> Result<ConfigLayer> {
+  Ok(ConfigLayer {
+    host: var("HOST", str::parse)?,
+    port: var("PORT", str::parse)?,
+    listen: var("LISTEN", list)?,
+    max_idle_time: var("MAX_IDLE_TIME", humantime::parse_duration)?,
+    max_unresponsive_time: var("MAX_UNRESPONSIVE_TIME", humantime::parse_duration)?,
+    max_packet_size: var("MAX_PACKET_SIZE", str::parse)?,
+    max_requests: var("MAX_REQUESTS", str::parse)?,
+    request_rate: var("REQUEST_RATE", str::parse)?,
+    realm_list_rate: var("REALM_LIST_RATE", str::parse)?,
+    realm_connect_rate: var("REALM_CONNECT_RATE", str::parse)?,
+    max_connections: var("MAX_CONNECTIONS", str::parse)?,
+    max_connections_per_ip: var("MAX_CONNECTIONS_PER_IP", str::parse)?,
+    connection_rate: var("CONNECTION_RATE", str::parse)?,
+    connection_rate_per_ip: var("CONNECTION_RATE_PER_IP", str::parse)?,
+    access_list: var("ACCESS_LIST", |value| Ok::<_, String>(PathBuf::from(value)))?,
+    staff: var("STAFF", list)?,
+    vip: var("VIP", list)?,
+    ipv4_prefix_len: var("IPV4_PREFIX_LEN", str::parse)?,
+    ipv6_prefix_len: var("IPV6_PREFIX_LEN", str::parse)?,
+    ignore_unknown_packets: var("IGNORE_UNKNOWN_PACKETS", str::parse)?,
+    require_handshake: var("REQUIRE_HANDSHAKE", str::parse)?,
+    welcome_packet: var("WELCOME_PACKET", str::parse)?,
+    xor_key: var("XOR_KEY", str::parse)?,
+    xor_key_file: var("XOR_KEY_FILE", |value| Ok::<_, String>(PathBuf::from(value)))?,
+    versions: None,
+    patch_server: None,
+    profile: var("PROFILE", str::parse)?,
+    profiles: None,
+    ban_threshold: var("BAN_THRESHOLD", str::parse)?,
+    ban_duration: var("BAN_DURATION", humantime::parse_duration)?,
+    ban_list: var("BAN_LIST", |value| Ok::<_, String>(PathBuf::from(value)))?,
+    strike_decay: var("STRIKE_DECAY", humantime::parse_duration)?,
+    strike_weights: None,
+    extension_timeout: var("EXTENSION_TIMEOUT", humantime::parse_duration)?,
+    extension_fallback: var("EXTENSION_FALLBACK", str::parse)?,
+    scripts: None,
+    listeners: None,
+    rpc_host: var("RPC_HOST", str::parse)?,
+    rpc_port: var("RPC_PORT", str::parse)?,
+    rpc_admin: var("RPC_ADMIN", str::parse)?,
+    static_realm_policy: var("STATIC_REALM_POLICY", str::parse)?,
+    realm_ttl: var("REALM_TTL", humantime::parse_duration)?,
+    realm_eviction_delay: var("REALM_EVICTION_DELAY", humantime::parse_duration)?,
+    stale_realms: var("STALE_REALMS", str::parse)?,
+    maintenance_display: var("MAINTENANCE_DISPLAY", str::parse)?,
+    realm_overflow: var("REALM_OVERFLOW", str::parse)?,
+    vip_overflow_margin: var("VIP_OVERFLOW_MARGIN", str::parse)?,
+    realms: None,
+    realm_groups: None,
+  })
+}
+
+/// Returns the configuration file path, if specified by `MUCS_CONFIG`.
+pub fn config_path() -> Option<PathBuf> {
+  env::var_os(format!("{}CONFIG", PREFIX)).map(PathBuf::from)
+}
+
+/// Parses a comma separated list.
+fn list<T: FromStr>(value: &str) -> std::result::Result<Vec<T>, T::Err> {
+  value
+    .split(',')
+    .map(str::trim)
+    .filter(|item| !item.is_empty())
+    .map(str::parse)
+    .collect()
+}
+
+/// Reads and parses an environment variable, if it is defined.
+fn var<T, E, F>(name: &str, parse: F) -> Result<Option<T>>
+where
+  F: FnOnce(&str) -> std::result::Result<T, E>,
+  E: Display,
+{
+  let name = format!("{}{}", PREFIX, name);
+  match env::var(&name) {
+    Ok(value) => parse(&value)
+      .map(Some)
+      .map_err(|error| format_err!("Invalid environment variable {}; {}", name, error)),
+    Err(VarError::NotPresent) => Ok(None),
+    Err(VarError::NotUnicode(_)) => Err(format_err!(
+      "Invalid environment variable {}; not valid unicode",
+      name
+    )),
+  }
+}
@@ -0,0 +1,190 @@
+use super::{ConfigLayer, ListenerDefinition, ProfileDefinition};
+use super::{RealmDefinition, RealmGroupDefinition};
+use crate::service::{ExtensionFallback, PatchServer, StrikeWeights, VersionRule};
+use crate::service::{ScriptPaths, WelcomePacket, XorKey};
+use crate::state::{MaintenanceDisplay, RealmOverflow, StaleRealmDisplay, StaticRealmPolicy};
+use crate::util::{IpNetwork, RateLimit};
+use crate::Result;
+use failure::ResultExt;
+use serde::{de, Deserialize, Deserializer};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::{fs, time::Duration};
+
+/// The layout of a configuration file.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+  connect: ConnectSection,
+  bans: BansSection,
+  extensions: ExtensionsSection,
+  rpc: RpcSection,
+  versions: Option<Vec<VersionRule>>,
+  patch: Option<PatchServer>,
+  scripts: Option<ScriptPaths>,
+  profiles: Option<Vec<ProfileDefinition>>,
+  listeners: Option<Vec<ListenerDefinition>>,
+  realms: Option<Vec<RealmDefinition>>,
+  #[serde(rename = "realmGroups")]
+  realm_groups: Option<Vec<RealmGroupDefinition>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+struct ConnectSection {
+  host: Option<IpAddr>,
+  port: Option<u16>,
+  listen: Option<Vec<SocketAddr>>,
+  #[serde(deserialize_with = "duration")]
+  max_idle_time: Option<Duration>,
+  #[serde(deserialize_with = "duration")]
+  max_unresponsive_time: Option<Duration>,
+  max_packet_size: Option<usize>,
+  max_requests: Option<usize>,
+  request_rate: Option<RateLimit>,
+  realm_list_rate: Option<RateLimit>,
+  realm_connect_rate: Option<RateLimit>,
+  max_connections: Option<usize>,
+  max_connections_per_ip: Option<usize>,
+  connection_rate: Option<RateLimit>,
+  connection_rate_per_ip: Option<RateLimit>,
+  access_list: Option<PathBuf>,
+  staff: Option<Vec<IpNetwork>>,
+  vip: Option<Vec<IpNetwork>>,
+  ipv4_prefix_len: Option<u8>,
+  ipv6_prefix_len: Option<u8>,
+  ignore_unknown_packets: Option<bool>,
+  require_handshake: Option<bool>,
+  welcome_packet: Option<WelcomePacket>,
+  xor_key: Option<XorKey>,
+  xor_key_file: Option<PathBuf>,
+  profile: Option<String>,
+  maintenance_display: Option<MaintenanceDisplay>,
+  realm_overflow: Option<RealmOverflow>,
+  vip_overflow_margin: Option<usize>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+struct BansSection {
+  threshold: Option<u32>,
+  #[serde(deserialize_with = "duration")]
+  duration: Option<Duration>,
+  list: Option<PathBuf>,
+  #[serde(deserialize_with = "duration")]
+  strike_decay: Option<Duration>,
+  strike_weights: Option<StrikeWeights>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+struct ExtensionsSection {
+  #[serde(deserialize_with = "duration")]
+  timeout: Option<Duration>,
+  fallback: Option<ExtensionFallback>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+struct RpcSection {
+  host: Option<String>,
+  port: Option<u16>,
+  admin: Option<bool>,
+  static_realm_policy: Option<StaticRealmPolicy>,
+  #[serde(deserialize_with = "duration")]
+  realm_ttl: Option<Duration>,
+  #[serde(deserialize_with = "duration")]
+  realm_eviction_delay: Option<Duration>,
+  stale_realms: Option<StaleRealmDisplay>,
+}
+
+impl From<ConfigFile> for ConfigLayer {
+  fn from(file: ConfigFile) -> Self {
+    let ConfigFile {
+      connect,
+      bans,
+      extensions,
+      rpc,
+      versions,
+      patch,
+      scripts,
+      profiles,
+      listeners,
+      realms,
+      realm_groups,
+    } = file;
+    ConfigLayer {
+      host: connect.host,
+      port: connect.port,
+      listen: connect.listen,
+      max_idle_time: connect.max_idle_time,
+      max_unresponsive_time: connect.max_unresponsive_time,
+      max_packet_size: connect.max_packet_size,
+      max_requests: connect.max_requests,
+      request_rate: connect.request_rate,
+      realm_list_rate: connect.realm_list_rate,
+      realm_connect_rate: connect.realm_connect_rate,
+      max_connections: connect.max_connections,
+      max_connections_per_ip: connect.max_connections_per_ip,
+      connection_rate: connect.connection_rate,
+      connection_rate_per_ip: connect.connection_rate_per_ip,
+      access_list: connect.access_list,
+      staff: connect.staff,
+      vip: connect.vip,
+      ipv4_prefix_len: connect.ipv4_prefix_len,
+      ipv6_prefix_len: connect.ipv6_prefix_len,
+      ignore_unknown_packets: connect.ignore_unknown_packets,
+      require_handshake: connect.require_handshake,
+      welcome_packet: connect.welcome_packet,
+      xor_key: connect.xor_key,
+      xor_key_file: connect.xor_key_file,
+      versions,
+      patch_server: patch,
+      profile: connect.profile,
+      profiles,
+      ban_threshold: bans.threshold,
+      ban_duration: bans.duration,
+      ban_list: bans.list,
+      strike_decay: bans.strike_decay,
+      strike_weights: bans.strike_weights,
+      extension_timeout: extensions.timeout,
+      extension_fallback: extensions.fallback,
+      scripts,
+      listeners,
+      rpc_host: rpc.host,
+      rpc_port: rpc.port,
+      rpc_admin: rpc.admin,
+      static_realm_policy: rpc.static_realm_policy,
+      realm_ttl: rpc.realm_ttl,
+      realm_eviction_delay: rpc.realm_eviction_delay,
+      stale_realms: rpc.stale_realms,
+      maintenance_display: connect.maintenance_display,
+      realm_overflow: connect.realm_overflow,
+      vip_overflow_margin: connect.vip_overflow_margin,
+      realms,
+      realm_groups,
+    }
+  }
+}
+
+/// Loads a configuration layer from a TOML file.
+pub fn load(path: &Path) -> Result<ConfigLayer> {
+  let content = fs::read_to_string(path)
+    .with_context(|_| format!("Failed to read config file {}", path.display()))?;
+  let file: ConfigFile = toml::from_str(&content)
+    .with_context(|_| format!("Invalid config file {}", path.display()))?;
+  Ok(file.into())
+}
+
+/// Deserializes a human readable duration (e.g `15s`).
+pub fn duration<'de, D>(deserializer: D) -> std::result::Result<Option<Duration>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  match Option::<String>::deserialize(deserializer)? {
+    Some(value) => humantime::parse_duration(&value)
+      .map(Some)
+      .map_err(de::Error::custom),
+    None => Ok(None),
+  }
+}
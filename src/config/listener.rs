@@ -0,0 +1,382 @@
+use super::{file, ConnectConfig, ProfileDefinition};
+use crate::service::{ConnectServiceConfig, ExtensionFallback, PatchServer, VersionPolicy};
+use crate::service::{ScriptPaths, VersionRule};
+use crate::service::{WelcomePacket, XorKey, STANDARD_LAYOUT};
+use crate::util::{IpNetwork, RateLimit};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// The name of the listener bound to the primary client addresses.
+pub const DEFAULT_LISTENER: &str = "default";
+
+/// A client listener declared in the configuration.
+///
+/// Any omitted limits are inherited from the `[connect]` section.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct ListenerDefinition {
+  pub name: String,
+  pub addresses: Vec<SocketAddr>,
+  #[serde(default, deserialize_with = "file::duration")]
+  pub max_idle_time: Option<Duration>,
+  #[serde(default, deserialize_with = "file::duration")]
+  pub max_unresponsive_time: Option<Duration>,
+  #[serde(default)]
+  pub max_packet_size: Option<usize>,
+  #[serde(default)]
+  pub max_requests: Option<usize>,
+  #[serde(default)]
+  pub request_rate: Option<RateLimit>,
+  #[serde(default)]
+  pub realm_list_rate: Option<RateLimit>,
+  #[serde(default)]
+  pub realm_connect_rate: Option<RateLimit>,
+  #[serde(default)]
+  pub max_connections: Option<usize>,
+  #[serde(default)]
+  pub max_connections_per_ip: Option<usize>,
+  #[serde(default)]
+  pub connection_rate: Option<RateLimit>,
+  #[serde(default)]
+  pub connection_rate_per_ip: Option<RateLimit>,
+  #[serde(default)]
+  pub access_list: Option<PathBuf>,
+  #[serde(default)]
+  pub staff: Option<Vec<IpNetwork>>,
+  #[serde(default)]
+  pub vip: Option<Vec<IpNetwork>>,
+  #[serde(default)]
+  pub ipv4_prefix_len: Option<u8>,
+  #[serde(default)]
+  pub ipv6_prefix_len: Option<u8>,
+  #[serde(default)]
+  pub ignore_unknown_packets: Option<bool>,
+  #[serde(default)]
+  pub require_handshake: Option<bool>,
+  #[serde(default)]
+  pub welcome_packet: Option<WelcomePacket>,
+  #[serde(default)]
+  pub xor_key: Option<XorKey>,
+  #[serde(default)]
+  pub xor_key_file: Option<PathBuf>,
+  #[serde(default)]
+  pub versions: Option<Vec<VersionRule>>,
+  #[serde(default)]
+  pub patch_server: Option<PatchServer>,
+  #[serde(default)]
+  pub profile: Option<String>,
+  #[serde(default)]
+  pub scripts: Option<ScriptPaths>,
+  #[serde(default)]
+  pub check_clients_per_ip: Option<bool>,
+}
+
+/// The resolved configuration of a client listener.
+#[derive(Debug, Clone)]
+pub struct ListenerConfig {
+  pub name: String,
+  pub sockets: Vec<SocketAddr>,
+  pub max_idle_time: Duration,
+  pub max_unresponsive_time: Duration,
+  pub max_packet_size: usize,
+  pub max_requests: usize,
+  pub request_rate: Option<RateLimit>,
+  pub realm_list_rate: Option<RateLimit>,
+  pub realm_connect_rate: Option<RateLimit>,
+  pub max_connections: usize,
+  pub max_connections_per_ip: usize,
+  pub connection_rate: Option<RateLimit>,
+  pub connection_rate_per_ip: Option<RateLimit>,
+  pub access_list: Option<PathBuf>,
+  pub staff: Vec<IpNetwork>,
+  pub vip: Vec<IpNetwork>,
+  pub ipv4_prefix_len: u8,
+  pub ipv6_prefix_len: u8,
+  pub ignore_unknown_packets: bool,
+  pub require_handshake: bool,
+  pub welcome_packet: Option<WelcomePacket>,
+  pub xor_key: Option<XorKey>,
+  pub xor_key_file: Option<PathBuf>,
+  pub versions: Vec<VersionRule>,
+  pub patch_server: Option<PatchServer>,
+  pub profile: Option<String>,
+  pub packet_layout: String,
+  pub extension_timeout: Duration,
+  pub extension_fallback: ExtensionFallback,
+  pub scripts: ScriptPaths,
+  pub check_clients_per_ip: bool,
+}
+
+impl ListenerConfig {
+  /// Returns the listener bound to the primary client addresses.
+  pub fn primary(config: &ConnectConfig) -> Self {
+    let mut listener = Self::base(config);
+    listener.apply_profile(config, config.profile.as_ref());
+    listener
+  }
+
+  /// Returns a listener definition, inheriting any omitted values.
+  ///
+  /// Values are resolved in the order of the `[connect]` section, the listener's profile (or the
+  /// primary one) and lastly the definition itself.
+  pub fn inherit(config: &ConnectConfig, definition: &ListenerDefinition) -> Self {
+    let mut listener = ListenerConfig {
+      name: definition.name.clone(),
+      sockets: definition.addresses.clone(),
+      ..Self::base(config)
+    };
+    listener.apply_profile(config, definition.profile.as_ref().or(config.profile.as_ref()));
+    listener.apply_definition(definition);
+    listener
+  }
+
+  /// Returns the primary listener, without any profile.
+  fn base(config: &ConnectConfig) -> Self {
+    ListenerConfig {
+      name: DEFAULT_LISTENER.into(),
+      sockets: config.sockets(),
+      max_idle_time: config.max_idle_time,
+      max_unresponsive_time: config.max_unresponsive_time,
+      max_packet_size: config.max_packet_size,
+      max_requests: config.max_requests,
+      request_rate: config.request_rate,
+      realm_list_rate: config.realm_list_rate,
+      realm_connect_rate: config.realm_connect_rate,
+      max_connections: config.max_connections,
+      max_connections_per_ip: config.max_connections_per_ip,
+      connection_rate: config.connection_rate,
+      connection_rate_per_ip: config.connection_rate_per_ip,
+      access_list: config.access_list.clone(),
+      staff: config.staff.clone(),
+      vip: config.vip.clone(),
+      ipv4_prefix_len: config.ipv4_prefix_len,
+      ipv6_prefix_len: config.ipv6_prefix_len,
+      ignore_unknown_packets: config.ignore_unknown_packets,
+      require_handshake: config.require_handshake,
+      welcome_packet: config.welcome_packet.clone(),
+      xor_key: config.xor_key,
+      xor_key_file: config.xor_key_file.clone(),
+      versions: config.versions.clone(),
+      patch_server: config.patch_server.clone(),
+      profile: None,
+      packet_layout: STANDARD_LAYOUT.into(),
+      extension_timeout: config.extension_timeout,
+      extension_fallback: config.extension_fallback.clone(),
+      scripts: config.scripts.clone(),
+      check_clients_per_ip: true,
+    }
+  }
+
+  /// Applies the values of a profile, if it exists.
+  fn apply_profile(&mut self, config: &ConnectConfig, name: Option<&String>) {
+    self.profile = name.cloned();
+    let profile = match name.and_then(|name| config.profile_definition(name)) {
+      Some(profile) => profile,
+      None => return,
+    };
+
+    let ProfileDefinition {
+      layout,
+      versions,
+      patch_server,
+      require_handshake,
+      welcome_packet,
+      xor_key,
+      xor_key_file,
+      ..
+    } = profile;
+
+    self.packet_layout = layout.clone();
+    if let Some(ref versions) = versions {
+      self.versions = versions.clone();
+    }
+    if patch_server.is_some() {
+      self.patch_server = patch_server.clone();
+    }
+    if let Some(require_handshake) = *require_handshake {
+      self.require_handshake = require_handshake;
+    }
+    if welcome_packet.is_some() {
+      self.welcome_packet = welcome_packet.clone();
+    }
+    self.apply_xor_key(*xor_key, xor_key_file);
+  }
+
+  /// Applies any values specified by a listener definition.
+  fn apply_definition(&mut self, definition: &ListenerDefinition) {
+    override_fields!(
+      self,
+      definition,
+      max_idle_time,
+      max_unresponsive_time,
+      max_packet_size,
+      max_requests,
+      max_connections,
+      max_connections_per_ip,
+      ipv4_prefix_len,
+      ipv6_prefix_len,
+      ignore_unknown_packets,
+      require_handshake,
+      check_clients_per_ip
+    );
+    self.request_rate = definition.request_rate.or(self.request_rate);
+    self.realm_list_rate = definition.realm_list_rate.or(self.realm_list_rate);
+    self.realm_connect_rate = definition
+      .realm_connect_rate
+      .or(self.realm_connect_rate);
+    self.connection_rate = definition.connection_rate.or(self.connection_rate);
+    self.connection_rate_per_ip = definition
+      .connection_rate_per_ip
+      .or(self.connection_rate_per_ip);
+    if definition.access_list.is_some() {
+      self.access_list = definition.access_list.clone();
+    }
+    if let Some(ref staff) = definition.staff {
+      self.staff = staff.clone();
+    }
+    if let Some(ref vip) = definition.vip {
+      self.vip = vip.clone();
+    }
+    if definition.welcome_packet.is_some() {
+      self.welcome_packet = definition.welcome_packet.clone();
+    }
+    if let Some(ref versions) = definition.versions {
+      self.versions = versions.clone();
+    }
+    if definition.patch_server.is_some() {
+      self.patch_server = definition.patch_server.clone();
+    }
+    self.apply_xor_key(definition.xor_key, &definition.xor_key_file);
+    if let Some(ref scripts) = definition.scripts {
+      self.scripts = scripts.clone();
+    }
+  }
+
+  /// Replaces the XOR key and key file, if either is specified.
+  fn apply_xor_key(&mut self, key: Option<XorKey>, file: &Option<PathBuf>) {
+    if key.is_some() || file.is_some() {
+      self.xor_key = key;
+      self.xor_key_file = file.clone();
+    }
+  }
+}
+
+impl ConnectServiceConfig for ListenerConfig {
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn sockets(&self) -> Vec<SocketAddr> {
+    self.sockets.clone()
+  }
+
+  fn max_idle_time(&self) -> Duration {
+    self.max_idle_time
+  }
+
+  fn max_unresponsive_time(&self) -> Duration {
+    self.max_unresponsive_time
+  }
+
+  fn max_packet_size(&self) -> usize {
+    self.max_packet_size
+  }
+
+  fn max_requests(&self) -> usize {
+    self.max_requests
+  }
+
+  fn max_connections(&self) -> usize {
+    self.max_connections
+  }
+
+  fn max_connections_per_ip(&self) -> usize {
+    self.max_connections_per_ip
+  }
+
+  fn request_rate(&self) -> Option<RateLimit> {
+    self.request_rate
+  }
+
+  fn realm_list_rate(&self) -> Option<RateLimit> {
+    self.realm_list_rate
+  }
+
+  fn realm_connect_rate(&self) -> Option<RateLimit> {
+    self.realm_connect_rate
+  }
+
+  fn connection_rate(&self) -> Option<RateLimit> {
+    self.connection_rate
+  }
+
+  fn connection_rate_per_ip(&self) -> Option<RateLimit> {
+    self.connection_rate_per_ip
+  }
+
+  fn access_list(&self) -> Option<PathBuf> {
+    self.access_list.clone()
+  }
+
+  fn staff(&self) -> Vec<IpNetwork> {
+    self.staff.clone()
+  }
+
+  fn vip(&self) -> Vec<IpNetwork> {
+    self.vip.clone()
+  }
+
+  fn ipv4_prefix_len(&self) -> u8 {
+    self.ipv4_prefix_len
+  }
+
+  fn ipv6_prefix_len(&self) -> u8 {
+    self.ipv6_prefix_len
+  }
+
+  fn ignore_unknown_packets(&self) -> bool {
+    self.ignore_unknown_packets
+  }
+
+  fn require_handshake(&self) -> bool {
+    self.require_handshake
+  }
+
+  fn welcome_packet(&self) -> Option<WelcomePacket> {
+    self.welcome_packet.clone()
+  }
+
+  fn versions(&self) -> VersionPolicy {
+    VersionPolicy::new(self.versions.clone(), self.patch_server.clone())
+  }
+
+  fn packet_layout(&self) -> &str {
+    &self.packet_layout
+  }
+
+  fn xor_key(&self) -> Option<XorKey> {
+    self.xor_key
+  }
+
+  fn xor_key_file(&self) -> Option<PathBuf> {
+    self.xor_key_file.clone()
+  }
+
+  fn extension_timeout(&self) -> Duration {
+    self.extension_timeout
+  }
+
+  fn extension_fallback(&self) -> ExtensionFallback {
+    self.extension_fallback.clone()
+  }
+
+  fn scripts(&self) -> ScriptPaths {
+    self.scripts.clone()
+  }
+
+  fn check_clients_per_ip(&self) -> bool {
+    self.check_clients_per_ip
+  }
+}
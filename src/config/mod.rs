@@ -0,0 +1,430 @@
+use crate::service::{BanPolicy, ExtensionFallback, PatchServer, RealmExpiry, RpcServiceConfig};
+use crate::service::{ConnectExtensions, StrikeWeights};
+use crate::service::{ScriptPaths, VersionRule, WelcomePacket, XorKey};
+use crate::state::{MaintenanceDisplay, RealmGroup, RealmGroupId, RealmOrigin, RealmServer};
+use crate::state::{RealmOverflow, RealmState, StaleRealmDisplay, StaticRealmPolicy};
+use crate::util::{IpNetwork, RateLimit};
+use crate::Result;
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[cfg(feature = "build-binary")]
+pub use self::args::ConnectArgs;
+pub use self::listener::{ListenerConfig, ListenerDefinition};
+pub use self::profile::ProfileDefinition;
+pub use self::validate::{ConfigIssue, ConfigReport, Severity};
+
+/// Overrides each field of a target with the layer's value, if specified.
+macro_rules! override_fields {
+  ($target:expr, $layer:expr, $($field:ident),*) => {
+    $(if let Some(value) = $layer.$field {
+      $target.$field = value;
+    })*
+  };
+}
+
+#[cfg(feature = "build-binary")]
+mod args;
+mod env;
+mod file;
+mod listener;
+mod profile;
+mod validate;
+
+/// The server configuration.
+#[derive(Debug, Clone)]
+pub struct ConnectConfig {
+  pub host: IpAddr,
+  pub port: u16,
+  pub listen: Vec<SocketAddr>,
+  pub max_idle_time: Duration,
+  pub max_unresponsive_time: Duration,
+  pub max_packet_size: usize,
+  pub max_requests: usize,
+  pub request_rate: Option<RateLimit>,
+  pub realm_list_rate: Option<RateLimit>,
+  pub realm_connect_rate: Option<RateLimit>,
+  pub max_connections: usize,
+  pub max_connections_per_ip: usize,
+  pub connection_rate: Option<RateLimit>,
+  pub connection_rate_per_ip: Option<RateLimit>,
+  pub access_list: Option<PathBuf>,
+  pub staff: Vec<IpNetwork>,
+  pub vip: Vec<IpNetwork>,
+  pub ipv4_prefix_len: u8,
+  pub ipv6_prefix_len: u8,
+  pub ignore_unknown_packets: bool,
+  pub require_handshake: bool,
+  pub welcome_packet: Option<WelcomePacket>,
+  pub xor_key: Option<XorKey>,
+  pub xor_key_file: Option<PathBuf>,
+  pub versions: Vec<VersionRule>,
+  pub patch_server: Option<PatchServer>,
+  pub profile: Option<String>,
+  pub profiles: Vec<ProfileDefinition>,
+  pub ban_threshold: u32,
+  pub ban_duration: Duration,
+  pub ban_list: Option<PathBuf>,
+  pub strike_decay: Duration,
+  pub strike_weights: StrikeWeights,
+  pub extension_timeout: Duration,
+  pub extension_fallback: ExtensionFallback,
+  pub scripts: ScriptPaths,
+  pub listeners: Vec<ListenerDefinition>,
+  pub rpc_host: String,
+  pub rpc_port: u16,
+  pub rpc_admin: bool,
+  pub static_realm_policy: StaticRealmPolicy,
+  pub realm_ttl: Option<Duration>,
+  pub realm_eviction_delay: Duration,
+  pub stale_realms: StaleRealmDisplay,
+  pub maintenance_display: MaintenanceDisplay,
+  pub realm_overflow: RealmOverflow,
+  pub vip_overflow_margin: usize,
+  pub realms: Vec<RealmDefinition>,
+  pub realm_groups: Vec<RealmGroupDefinition>,
+}
+
+impl Default for ConnectConfig {
+  fn default() -> Self {
+    ConnectConfig {
+      host: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+      port: 2004,
+      listen: Vec::new(),
+      max_idle_time: Duration::from_secs(100),
+      max_unresponsive_time: Duration::from_secs(60),
+      max_packet_size: 6,
+      max_requests: 0,
+      request_rate: Some(RateLimit {
+        count: 10,
+        period: Duration::from_secs(1),
+      }),
+      realm_list_rate: Some(RateLimit {
+        count: 5,
+        period: Duration::from_secs(10),
+      }),
+      realm_connect_rate: None,
+      max_connections: 1000,
+      max_connections_per_ip: 1,
+      connection_rate: None,
+      connection_rate_per_ip: None,
+      access_list: None,
+      staff: Vec::new(),
+      vip: Vec::new(),
+      ipv4_prefix_len: 32,
+      ipv6_prefix_len: 64,
+      ignore_unknown_packets: false,
+      require_handshake: false,
+      welcome_packet: None,
+      xor_key: None,
+      xor_key_file: None,
+      versions: Vec::new(),
+      patch_server: None,
+      profile: None,
+      profiles: Vec::new(),
+      ban_threshold: 0,
+      ban_duration: Duration::from_secs(60 * 60),
+      ban_list: None,
+      strike_decay: Duration::from_secs(60),
+      strike_weights: StrikeWeights::default(),
+      extension_timeout: Duration::from_secs(2),
+      extension_fallback: ExtensionFallback::Ignore,
+      scripts: ScriptPaths::default(),
+      listeners: Vec::new(),
+      rpc_host: "0.0.0.0".into(),
+      rpc_port: 0,
+      rpc_admin: false,
+      static_realm_policy: StaticRealmPolicy::Reject,
+      realm_ttl: None,
+      realm_eviction_delay: Duration::from_secs(60),
+      stale_realms: StaleRealmDisplay::Full,
+      maintenance_display: MaintenanceDisplay::Full,
+      realm_overflow: RealmOverflow::Allow,
+      vip_overflow_margin: 0,
+      realms: Vec::new(),
+      realm_groups: Vec::new(),
+    }
+  }
+}
+
+impl ConnectConfig {
+  /// Loads a TOML configuration file, using defaults for any omitted values.
+  pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+    let mut config = ConnectConfig::default();
+    config.apply(file::load(path.as_ref())?);
+    Ok(config)
+  }
+
+  /// Loads the configuration from an optional file and the environment.
+  ///
+  /// Values are resolved in the order of defaults, the configuration file (either `path` or
+  /// `MUCS_CONFIG`) and lastly any `MUCS_*` environment variables.
+  pub fn load(path: Option<&Path>) -> Result<Self> {
+    let mut config = ConnectConfig::default();
+    if let Some(path) = path.map(PathBuf::from).or_else(env::config_path) {
+      config.apply(file::load(&path)?);
+    }
+    config.apply(env::load()?);
+    Ok(config)
+  }
+
+  /// Replaces any values specified by the layer.
+  pub fn apply(&mut self, layer: ConfigLayer) {
+    override_fields!(
+      self,
+      layer,
+      host,
+      port,
+      listen,
+      max_idle_time,
+      max_unresponsive_time,
+      max_packet_size,
+      max_requests,
+      max_connections,
+      max_connections_per_ip,
+      staff,
+      vip,
+      ipv4_prefix_len,
+      ipv6_prefix_len,
+      ignore_unknown_packets,
+      require_handshake,
+      versions,
+      profiles,
+      ban_threshold,
+      ban_duration,
+      strike_decay,
+      strike_weights,
+      extension_timeout,
+      extension_fallback,
+      scripts,
+      listeners,
+      rpc_host,
+      rpc_port,
+      rpc_admin,
+      static_realm_policy,
+      realm_eviction_delay,
+      stale_realms,
+      maintenance_display,
+      realm_overflow,
+      vip_overflow_margin,
+      realms,
+      realm_groups
+    );
+    self.request_rate = layer.request_rate.or(self.request_rate);
+    self.realm_list_rate = layer.realm_list_rate.or(self.realm_list_rate);
+    self.realm_connect_rate = layer.realm_connect_rate.or(self.realm_connect_rate);
+    self.realm_ttl = layer.realm_ttl.or(self.realm_ttl);
+    self.connection_rate = layer.connection_rate.or(self.connection_rate);
+    self.connection_rate_per_ip = layer.connection_rate_per_ip.or(self.connection_rate_per_ip);
+    self.access_list = layer.access_list.or_else(|| self.access_list.take());
+    self.welcome_packet = layer.welcome_packet.or_else(|| self.welcome_packet.take());
+    // A key replaces any key file of a previous layer, and vice versa
+    if layer.xor_key.is_some() || layer.xor_key_file.is_some() {
+      self.xor_key = layer.xor_key;
+      self.xor_key_file = layer.xor_key_file;
+    }
+    self.patch_server = layer.patch_server.or_else(|| self.patch_server.take());
+    self.profile = layer.profile.or_else(|| self.profile.take());
+    self.ban_list = layer.ban_list.or_else(|| self.ban_list.take());
+  }
+
+  /// Returns the primary client listener, followed by any declared listeners.
+  pub fn client_listeners(&self) -> Vec<ListenerConfig> {
+    iter::once(ListenerConfig::primary(self))
+      .chain(
+        self
+          .listeners
+          .iter()
+          .map(|definition| ListenerConfig::inherit(self, definition)),
+      ).collect()
+  }
+
+  /// Returns a protocol profile by its name.
+  pub fn profile_definition(&self, name: &str) -> Option<&ProfileDefinition> {
+    self.profiles.iter().find(|profile| profile.name == name)
+  }
+
+  /// Returns the static realms of the configuration.
+  pub(crate) fn static_realms(&self) -> Vec<RealmServer> {
+    self.realms.iter().map(RealmServer::from).collect()
+  }
+
+  /// Returns the realm groups of the configuration.
+  pub(crate) fn realm_groups(&self) -> Vec<RealmGroup> {
+    self.realm_groups.iter().map(RealmGroup::from).collect()
+  }
+
+  /// Returns the policy for banning abusive clients.
+  pub(crate) fn ban_policy(&self) -> BanPolicy {
+    BanPolicy {
+      threshold: self.ban_threshold,
+      duration: self.ban_duration,
+      strike_decay: self.strike_decay,
+      weights: self.strike_weights,
+      list: self.ban_list.clone(),
+      ipv4_prefix_len: self.ipv4_prefix_len,
+      ipv6_prefix_len: self.ipv6_prefix_len,
+    }
+  }
+
+  /// Checks the configuration for any values that would fail at runtime, using only the
+  /// built-in packet layouts.
+  pub fn validate(&self) -> ConfigReport {
+    self.validate_with(&ConnectExtensions::new())
+  }
+
+  /// Checks the configuration for any values that would fail at runtime, using the packet layouts
+  /// registered as extensions.
+  pub fn validate_with(&self, extensions: &ConnectExtensions) -> ConfigReport {
+    validate::validate(self, extensions)
+  }
+
+  pub fn socket(&self) -> SocketAddr {
+    SocketAddr::new(self.host, self.port)
+  }
+
+  /// Returns the primary client address, followed by any additional ones.
+  pub fn sockets(&self) -> Vec<SocketAddr> {
+    iter::once(self.socket())
+      .chain(self.listen.iter().cloned())
+      .collect()
+  }
+}
+
+/// A partial configuration, overriding any values it specifies.
+#[derive(Debug, Default, Clone)]
+pub struct ConfigLayer {
+  pub host: Option<IpAddr>,
+  pub port: Option<u16>,
+  pub listen: Option<Vec<SocketAddr>>,
+  pub max_idle_time: Option<Duration>,
+  pub max_unresponsive_time: Option<Duration>,
+  pub max_packet_size: Option<usize>,
+  pub max_requests: Option<usize>,
+  pub request_rate: Option<RateLimit>,
+  pub realm_list_rate: Option<RateLimit>,
+  pub realm_connect_rate: Option<RateLimit>,
+  pub max_connections: Option<usize>,
+  pub max_connections_per_ip: Option<usize>,
+  pub connection_rate: Option<RateLimit>,
+  pub connection_rate_per_ip: Option<RateLimit>,
+  pub access_list: Option<PathBuf>,
+  pub staff: Option<Vec<IpNetwork>>,
+  pub vip: Option<Vec<IpNetwork>>,
+  pub ipv4_prefix_len: Option<u8>,
+  pub ipv6_prefix_len: Option<u8>,
+  pub ignore_unknown_packets: Option<bool>,
+  pub require_handshake: Option<bool>,
+  pub welcome_packet: Option<WelcomePacket>,
+  pub xor_key: Option<XorKey>,
+  pub xor_key_file: Option<PathBuf>,
+  pub versions: Option<Vec<VersionRule>>,
+  pub patch_server: Option<PatchServer>,
+  pub profile: Option<String>,
+  pub profiles: Option<Vec<ProfileDefinition>>,
+  pub ban_threshold: Option<u32>,
+  pub ban_duration: Option<Duration>,
+  pub ban_list: Option<PathBuf>,
+  pub strike_decay: Option<Duration>,
+  pub strike_weights: Option<StrikeWeights>,
+  pub extension_timeout: Option<Duration>,
+  pub extension_fallback: Option<ExtensionFallback>,
+  pub scripts: Option<ScriptPaths>,
+  pub listeners: Option<Vec<ListenerDefinition>>,
+  pub rpc_host: Option<String>,
+  pub rpc_port: Option<u16>,
+  pub rpc_admin: Option<bool>,
+  pub static_realm_policy: Option<StaticRealmPolicy>,
+  pub realm_ttl: Option<Duration>,
+  pub realm_eviction_delay: Option<Duration>,
+  pub stale_realms: Option<StaleRealmDisplay>,
+  pub maintenance_display: Option<MaintenanceDisplay>,
+  pub realm_overflow: Option<RealmOverflow>,
+  pub vip_overflow_margin: Option<usize>,
+  pub realms: Option<Vec<RealmDefinition>>,
+  pub realm_groups: Option<Vec<RealmGroupDefinition>>,
+}
+
+/// A realm server declared in the configuration.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RealmDefinition {
+  pub id: u16,
+  pub host: String,
+  pub port: u16,
+  pub capacity: usize,
+  #[serde(default)]
+  pub state: RealmState,
+}
+
+impl<'a> From<&'a RealmDefinition> for RealmServer {
+  fn from(definition: &'a RealmDefinition) -> Self {
+    RealmServer {
+      id: definition.id,
+      host: definition.host.clone(),
+      port: definition.port,
+      clients: 0,
+      capacity: definition.capacity,
+      origin: RealmOrigin::Static,
+      state: definition.state,
+    }
+  }
+}
+
+/// Display metadata of a realm group declared in the configuration.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RealmGroupDefinition {
+  pub id: RealmGroupId,
+  pub name: String,
+  #[serde(default)]
+  pub order: Option<u32>,
+  #[serde(default = "RealmGroupDefinition::visible")]
+  pub visible: bool,
+}
+
+impl RealmGroupDefinition {
+  fn visible() -> bool {
+    true
+  }
+}
+
+impl<'a> From<&'a RealmGroupDefinition> for RealmGroup {
+  fn from(definition: &'a RealmGroupDefinition) -> Self {
+    RealmGroup {
+      id: definition.id,
+      name: definition.name.clone(),
+      order: definition.order.unwrap_or_else(|| u32::from(definition.id)),
+      visible: definition.visible,
+    }
+  }
+}
+
+impl RpcServiceConfig for ConnectConfig {
+  fn host(&self) -> &str {
+    &self.rpc_host
+  }
+
+  fn port(&self) -> u16 {
+    self.rpc_port
+  }
+
+  fn admin(&self) -> bool {
+    self.rpc_admin
+  }
+
+  fn static_realm_policy(&self) -> StaticRealmPolicy {
+    self.static_realm_policy
+  }
+
+  fn realm_expiry(&self) -> Option<RealmExpiry> {
+    self.realm_ttl.map(|ttl| RealmExpiry {
+      ttl,
+      eviction_delay: self.realm_eviction_delay,
+      display: self.stale_realms,
+    })
+  }
+}
@@ -0,0 +1,31 @@
+use crate::service::{PatchServer, VersionRule, WelcomePacket, XorKey, STANDARD_LAYOUT};
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// A protocol profile, selecting the packet layout, XOR key and version handling of a client
+/// generation.
+///
+/// Any omitted values are inherited by the listeners using the profile.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct ProfileDefinition {
+  pub name: String,
+  #[serde(default = "standard_layout")]
+  pub layout: String,
+  #[serde(default)]
+  pub versions: Option<Vec<VersionRule>>,
+  #[serde(default)]
+  pub patch_server: Option<PatchServer>,
+  #[serde(default)]
+  pub require_handshake: Option<bool>,
+  #[serde(default)]
+  pub welcome_packet: Option<WelcomePacket>,
+  #[serde(default)]
+  pub xor_key: Option<XorKey>,
+  #[serde(default)]
+  pub xor_key_file: Option<PathBuf>,
+}
+
+fn standard_layout() -> String {
+  STANDARD_LAYOUT.into()
+}
@@ -0,0 +1,511 @@
+use super::listener::DEFAULT_LISTENER;
+use super::{ConnectConfig, ListenerConfig};
+use crate::service::{load_bans, AccessRules, ConnectExtensions, ScriptPaths, VersionAction};
+use crate::service::XorKey;
+use crate::state::{RealmOverflow, RealmServerId, REALM_GROUP_SIZE};
+use failure::Fail;
+use std::collections::HashSet;
+use std::{fmt, iter, time::Duration};
+
+/// Sizes of the client packets that must fit within `max_packet_size`.
+const CLIENT_PACKETS: &[(&str, usize)] = &[
+  // C1 header, code and a three byte version
+  ("ConnectServerRequest", 6),
+  // C1 header, code and subcode
+  ("RealmServerListRequest", 4),
+  // C1 header, code, subcode and a realm ID
+  ("RealmServerConnectRequest", 6),
+];
+
+/// The minimum requests required for a client to join a realm.
+const MIN_SESSION_REQUESTS: usize = 3;
+
+/// The severity of a configuration issue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+  Warning,
+  Error,
+}
+
+/// An issue found while validating a configuration.
+#[derive(Debug, Clone)]
+pub struct ConfigIssue {
+  pub severity: Severity,
+  pub key: String,
+  pub message: String,
+}
+
+impl fmt::Display for ConfigIssue {
+  fn fmt(&self, output: &mut fmt::Formatter) -> fmt::Result {
+    write!(output, "{}: {}", self.key, self.message)
+  }
+}
+
+/// The outcome of validating a configuration.
+#[derive(Fail, Debug, Clone, Default)]
+pub struct ConfigReport {
+  pub issues: Vec<ConfigIssue>,
+}
+
+impl ConfigReport {
+  /// Returns all issues preventing the configuration from being used.
+  pub fn errors(&self) -> impl Iterator<Item = &ConfigIssue> {
+    self.with_severity(Severity::Error)
+  }
+
+  /// Returns all issues which may cause unexpected behavior.
+  pub fn warnings(&self) -> impl Iterator<Item = &ConfigIssue> {
+    self.with_severity(Severity::Warning)
+  }
+
+  /// Returns whether the configuration is usable or not.
+  pub fn is_valid(&self) -> bool {
+    self.errors().next().is_none()
+  }
+
+  /// Returns the report as an error, if the configuration is invalid.
+  pub fn into_result(self) -> Result<(), Self> {
+    if self.is_valid() {
+      Ok(())
+    } else {
+      Err(self)
+    }
+  }
+
+  fn with_severity(&self, severity: Severity) -> impl Iterator<Item = &ConfigIssue> {
+    self
+      .issues
+      .iter()
+      .filter(move |issue| issue.severity == severity)
+  }
+
+  fn error<K: Into<String>>(&mut self, key: K, message: String) {
+    self.issues.push(ConfigIssue {
+      severity: Severity::Error,
+      key: key.into(),
+      message,
+    });
+  }
+
+  fn warning<K: Into<String>>(&mut self, key: K, message: String) {
+    self.issues.push(ConfigIssue {
+      severity: Severity::Warning,
+      key: key.into(),
+      message,
+    });
+  }
+}
+
+impl fmt::Display for ConfigReport {
+  fn fmt(&self, output: &mut fmt::Formatter) -> fmt::Result {
+    write!(output, "Invalid configuration")?;
+    for issue in self.errors() {
+      write!(output, "; {}", issue)?;
+    }
+    Ok(())
+  }
+}
+
+/// Validates the semantics of a configuration.
+pub fn validate(config: &ConnectConfig, extensions: &ConnectExtensions) -> ConfigReport {
+  let mut report = ConfigReport::default();
+  let mut names = HashSet::new();
+  let mut sockets = HashSet::new();
+
+  for listener in config.client_listeners() {
+    let section = if listener.name == DEFAULT_LISTENER {
+      "connect".to_string()
+    } else {
+      format!("listeners.{}", listener.name)
+    };
+
+    if !names.insert(listener.name.clone()) {
+      report.error(
+        "listeners.name",
+        format!("listener '{}' is declared more than once", listener.name),
+      );
+    }
+
+    if listener.sockets.is_empty() {
+      report.error(
+        format!("{}.addresses", section),
+        "listener has no addresses".into(),
+      );
+    }
+
+    for &socket in &listener.sockets {
+      if !sockets.insert(socket) {
+        report.error(
+          format!("{}.addresses", section),
+          format!("address {} is bound more than once", socket),
+        );
+      }
+
+      if config.rpc_port != 0 && config.rpc_port == socket.port() {
+        report.error(
+          "rpc.port",
+          format!(
+            "RPC port {} is already used by the client listener {}",
+            config.rpc_port, socket
+          ),
+        );
+      }
+    }
+
+    if let Some(ref profile) = listener.profile {
+      if config.profile_definition(profile).is_none() {
+        report.error(
+          format!("{}.profile", section),
+          format!("profile '{}' is not declared", profile),
+        );
+      }
+    }
+
+    validate_limits(&mut report, &section, &listener);
+  }
+
+  let mut profiles = HashSet::new();
+  for profile in &config.profiles {
+    if !profiles.insert(&profile.name) {
+      report.error(
+        "profiles.name",
+        format!("profile '{}' is declared more than once", profile.name),
+      );
+    }
+
+    if !extensions.has_layout(&profile.layout) {
+      report.error(
+        "profiles.layout",
+        format!(
+          "profile '{}' uses an unknown packet layout '{}'",
+          profile.name, profile.layout
+        ),
+      );
+    }
+  }
+
+  validate_bans(&mut report, config);
+  validate_extensions(&mut report, config);
+
+  if config.realm_ttl == Some(Duration::from_secs(0)) {
+    report.error("rpc.realmTtl", "a TTL of 0 marks realms stale immediately".into());
+  }
+
+  if config.vip_overflow_margin > 0 && config.realm_overflow == RealmOverflow::Allow {
+    report.warning(
+      "connect.vipOverflowMargin",
+      "a VIP margin has no effect when all clients may join full realms".into(),
+    );
+  } else if config.vip_overflow_margin > 0
+    && config
+      .client_listeners()
+      .iter()
+      .all(|listener| listener.vip.is_empty())
+  {
+    report.warning(
+      "connect.vipOverflowMargin",
+      "a VIP margin has no effect without any VIP networks".into(),
+    );
+  }
+
+  let mut realm_ids = HashSet::new();
+  for realm in &config.realms {
+    if !realm_ids.insert(realm.id) {
+      report.error("realms.id", format!("realm {} is declared more than once", realm.id));
+    }
+
+    if realm.host.is_empty() {
+      report.error("realms.host", format!("realm {} has no host", realm.id));
+    }
+
+    if realm.port == 0 {
+      report.error("realms.port", format!("realm {} has port 0", realm.id));
+    }
+
+    if realm.capacity == 0 {
+      report.warning(
+        "realms.capacity",
+        format!("realm {} with capacity 0 is always shown as full", realm.id),
+      );
+    }
+  }
+
+  let mut group_ids = HashSet::new();
+  for group in &config.realm_groups {
+    if !group_ids.insert(group.id) {
+      report.error(
+        "realmGroups.id",
+        format!("realm group {} is declared more than once", group.id),
+      );
+    }
+
+    if group.id > RealmServerId::max_value() / REALM_GROUP_SIZE {
+      report.warning(
+        "realmGroups.id",
+        format!("realm group {} contains no valid realm IDs", group.id),
+      );
+    }
+  }
+
+  report
+}
+
+/// Validates the policy for banning abusive clients.
+fn validate_bans(report: &mut ConfigReport, config: &ConnectConfig) {
+  if let Some(ref path) = config.ban_list {
+    if let Err(error) = load_bans(path) {
+      report.error("bans.list", error_chain(&error));
+    }
+  }
+
+  if config.ban_threshold == 0 {
+    return;
+  }
+
+  if config.ban_duration == Duration::from_secs(0) {
+    report.error("bans.duration", "a ban duration of 0 never bans a client".into());
+  }
+
+  if config.strike_decay == Duration::from_secs(0) {
+    report.error(
+      "bans.strikeDecay",
+      "a strike decay of 0 expires strikes immediately".into(),
+    );
+  }
+
+  let weights = &config.strike_weights;
+  if weights.unknown_packet == 0
+    && weights.invalid_packet == 0
+    && weights.max_packets_exceeded == 0
+    && weights.version_mismatch == 0
+  {
+    report.warning(
+      "bans.strikeWeights",
+      "every strike weight is 0; no client is ever banned".into(),
+    );
+  }
+}
+
+/// Validates the forwarding of packets to extension services.
+fn validate_extensions(report: &mut ConfigReport, config: &ConnectConfig) {
+  if config.extension_timeout == Duration::from_secs(0) {
+    report.error(
+      "extensions.timeout",
+      "a timeout of 0 never awaits an extension's response".into(),
+    );
+  }
+
+  if let Some(Err(error)) = config.extension_fallback.to_packet() {
+    report.error("extensions.fallback", format!("invalid fallback packet; {}", error));
+  }
+}
+
+/// Validates the scripts of a listener.
+#[cfg(feature = "scripting")]
+fn validate_scripts(report: &mut ConfigReport, key: String, scripts: &ScriptPaths) {
+  if let Err(error) = crate::service::ScriptHooks::check(scripts) {
+    report.error(key, error_chain(&error));
+  }
+}
+
+#[cfg(not(feature = "scripting"))]
+fn validate_scripts(report: &mut ConfigReport, key: String, scripts: &ScriptPaths) {
+  if !scripts.is_empty() {
+    report.error(key, "scripts require the 'scripting' feature".into());
+  }
+}
+
+/// Returns an error message, followed by its causes.
+fn error_chain(error: &failure::Error) -> String {
+  let causes = error.iter_causes().map(|cause| format!("; {}", cause));
+  iter::once(error.to_string()).chain(causes).collect()
+}
+
+/// Validates the client limits of a listener.
+fn validate_limits(report: &mut ConfigReport, section: &str, listener: &ListenerConfig) {
+  let key = |name: &str| format!("{}.{}", section, name);
+
+  if let Some(&(name, size)) = CLIENT_PACKETS
+    .iter()
+    .find(|&&(_, size)| listener.max_packet_size < size)
+  {
+    report.error(
+      key("maxPacketSize"),
+      format!(
+        "max_packet_size {} is smaller than a {} ({} bytes)",
+        listener.max_packet_size, name, size
+      ),
+    );
+  }
+
+  if listener.max_requests == 0 {
+    if listener.request_rate.is_none() {
+      report.warning(
+        key("maxRequests"),
+        "max_requests 0 without a request_rate leaves client requests unlimited".into(),
+      );
+    }
+  } else if listener.max_requests < MIN_SESSION_REQUESTS {
+    report.warning(
+      key("maxRequests"),
+      format!(
+        "max_requests {} is lower than the {} requests required to join a realm",
+        listener.max_requests, MIN_SESSION_REQUESTS
+      ),
+    );
+  }
+
+  if listener.max_connections == 0 {
+    report.error(
+      key("maxConnections"),
+      "max_connections 0 refuses every client".into(),
+    );
+  }
+
+  if listener.check_clients_per_ip {
+    if listener.max_connections_per_ip == 0 {
+      report.error(
+        key("maxConnectionsPerIp"),
+        "max_connections_per_ip 0 refuses every client".into(),
+      );
+    } else if listener.max_connections_per_ip > listener.max_connections {
+      report.warning(
+        key("maxConnectionsPerIp"),
+        format!(
+          "max_connections_per_ip {} exceeds max_connections {}",
+          listener.max_connections_per_ip, listener.max_connections
+        ),
+      );
+    }
+
+    if listener.ipv4_prefix_len > 32 {
+      report.error(
+        key("ipv4PrefixLen"),
+        format!("ipv4_prefix_len {} exceeds 32", listener.ipv4_prefix_len),
+      );
+    }
+
+    if listener.ipv6_prefix_len > 128 {
+      report.error(
+        key("ipv6PrefixLen"),
+        format!("ipv6_prefix_len {} exceeds 128", listener.ipv6_prefix_len),
+      );
+    }
+  }
+
+  let rates = [
+    ("connectionRate", listener.connection_rate),
+    ("connectionRatePerIp", listener.connection_rate_per_ip),
+    ("requestRate", listener.request_rate),
+    ("realmListRate", listener.realm_list_rate),
+    ("realmConnectRate", listener.realm_connect_rate),
+  ];
+  for &(name, rate) in &rates {
+    if let Some(rate) = rate {
+      if rate.count == 0 {
+        report.error(key(name), format!("rate {} refuses every client", rate));
+      } else if rate.period == Duration::from_secs(0) {
+        report.error(
+          key(name),
+          format!("rate {} has no period", rate),
+        );
+      }
+    }
+  }
+
+  if let Some(ref path) = listener.access_list {
+    if let Err(error) = AccessRules::load(path) {
+      report.error(key("accessList"), error_chain(&error));
+    }
+  }
+
+  if let Some(ref welcome) = listener.welcome_packet {
+    if let Err(error) = welcome.to_packet() {
+      report.error(key("welcomePacket"), format!("invalid welcome packet; {}", error));
+    }
+  }
+
+  match (listener.xor_key, &listener.xor_key_file) {
+    (Some(_), Some(_)) => report.error(
+      key("xorKey"),
+      "xor_key and xor_key_file are mutually exclusive".into(),
+    ),
+    (None, Some(path)) => if let Err(error) = XorKey::load(path) {
+      report.error(key("xorKeyFile"), error_chain(&error));
+    },
+    _ => (),
+  }
+
+  // Scripts of the primary listener are declared at the top level
+  let scripts_key = if listener.name == DEFAULT_LISTENER {
+    "scripts".to_string()
+  } else {
+    key("scripts")
+  };
+  validate_scripts(report, scripts_key, &listener.scripts);
+
+  // Versions of the primary listener are declared at the top level
+  let versions_key = if listener.name == DEFAULT_LISTENER {
+    "versions".to_string()
+  } else {
+    key("versions")
+  };
+
+  for rule in &listener.versions {
+    if rule.range.is_empty() {
+      report.error(
+        versions_key.clone(),
+        format!("version range {} is empty", rule.range),
+      );
+    }
+  }
+
+  let patch_key = if listener.name == DEFAULT_LISTENER {
+    "patch".to_string()
+  } else {
+    key("patchServer")
+  };
+
+  match listener.patch_server {
+    Some(ref patch) => {
+      if let Err(error) = patch.to_packet() {
+        report.error(patch_key, format!("invalid patch server; {}", error));
+      }
+    }
+    None => {
+      if listener
+        .versions
+        .iter()
+        .any(|rule| rule.action == VersionAction::Patch)
+      {
+        report.error(
+          versions_key.clone(),
+          "versions are redirected to a patch server, but none is configured".into(),
+        );
+      }
+    }
+  }
+
+  if !listener.versions.is_empty()
+    && listener
+      .versions
+      .iter()
+      .all(|rule| rule.action == VersionAction::Reject)
+  {
+    report.warning(versions_key, "no client version is accepted".into());
+  }
+
+  if listener.max_idle_time == Duration::from_secs(0) {
+    report.error(
+      key("maxIdleTime"),
+      "max_idle_time 0s disconnects every client immediately".into(),
+    );
+  }
+
+  if listener.max_unresponsive_time == Duration::from_secs(0) {
+    report.error(
+      key("maxUnresponsiveTime"),
+      "max_unresponsive_time 0s drops every client on its first response".into(),
+    );
+  }
+}
@@ -1,9 +1,28 @@
 use crate::service::{ConnectService, RpcService};
-use crate::state::RealmServerList;
+use crate::state::{ExtensionList, RealmServerList};
 use failure::ResultExt;
+use log::{info, warn};
 use std::sync::Arc;
 
-pub use crate::config::ConnectConfig;
+#[cfg(feature = "build-binary")]
+pub use crate::config::ConnectArgs;
+pub use crate::config::{
+  ConfigIssue, ConfigLayer, ConfigReport, ConnectConfig, ListenerConfig, ListenerDefinition,
+  ProfileDefinition, RealmDefinition, RealmGroupDefinition, Severity,
+};
+pub use crate::service::{
+  ClientError, ClientEventPlugin, ClientRequest, ClientSessionError, ClientVersion,
+  ConnectExtensions, ConnectServiceError, ExtensionFallback, LegacyLayout, PacketLayout,
+  PacketMiddleware, PacketResponder, PatchServer, ProtocolState, RealmFilter, ScriptPaths,
+  ServerError, StandardLayout, StrikeWeights, VersionAction, VersionRange, VersionRule,
+  WelcomePacket, XorKey, LEGACY_LAYOUT, STANDARD_LAYOUT,
+};
+#[cfg(feature = "scripting")]
+pub use crate::service::ScriptHooks;
+pub use crate::state::{MaintenanceDisplay, RealmGroup, RealmGroupId, RealmOrigin, RealmServer};
+pub use crate::state::{RealmOverflow, RealmServerId, RealmState, StaleRealmDisplay};
+pub use crate::state::{StaticRealmPolicy, REALM_GROUP_SIZE};
+pub use crate::util::EventArgs;
 
 #[macro_use]
 mod util;
@@ -12,7 +31,6 @@ mod service;
 mod state;
 
 // TODO: Fix local packet dependencies
-// TODO: Parse arguments from TOML as well
 // TODO: Disable connect service if RPC fails & vice versa?
 
 /// Default result type used.
@@ -22,23 +40,76 @@ type Result<T> = std::result::Result<T, failure::Error>;
 pub struct ConnectServer {
   connect_service: ConnectService,
   rpc_service: RpcService,
+  realms: RealmServerList,
+  extensions: ConnectExtensions,
 }
 
 impl ConnectServer {
-  /// Spawns a new Connect Server using defaults.
+  /// Spawns a new Connect Server, refusing any invalid configuration.
   pub fn spawn(config: ConnectConfig) -> Result<Self> {
+    Self::spawn_with(config, &ConnectExtensions::new())
+  }
+
+  /// Spawns a new Connect Server with extensions, refusing any invalid configuration.
+  pub fn spawn_with(config: ConnectConfig, extensions: &ConnectExtensions) -> Result<Self> {
+    Self::check_config(&config, extensions)?;
     let realms = RealmServerList::new();
-    let config = Arc::new(config);
+    realms.set_static(config.static_realms());
+    realms.set_groups(config.realm_groups());
+    realms.set_maintenance_display(config.maintenance_display);
+    realms.set_overflow(config.realm_overflow, config.vip_overflow_margin);
+    let extension_services = ExtensionList::new();
 
-    let connect_service = ConnectService::spawn(config.clone(), realms.clone());
-    let rpc_service = RpcService::spawn(config, realms);
+    let connect_service = ConnectService::spawn(
+      config.client_listeners(),
+      config.ban_policy(),
+      config.max_connections,
+      extensions,
+      realms.clone(),
+      extension_services.clone(),
+    )?;
+    let rpc_service = RpcService::spawn(Arc::new(config), realms.clone(), extension_services);
 
     Ok(ConnectServer {
       rpc_service,
       connect_service,
+      realms,
+      extensions: extensions.clone(),
     })
   }
 
+  /// Applies a new configuration to the running server.
+  ///
+  /// Connected clients are unaffected, static realms and realm groups are replaced, existing bans
+  /// and realm states changed at runtime are kept, and any changes to the listener addresses,
+  /// packet size or RPC service require a restart. An invalid configuration is refused.
+  pub fn reload(&self, config: ConnectConfig) -> Result<()> {
+    Self::check_config(&config, &self.extensions)?;
+    self.connect_service.reload(
+      &config.client_listeners(),
+      config.ban_policy(),
+      config.max_connections,
+    );
+    self.realms.set_static(config.static_realms());
+    self.realms.set_groups(config.realm_groups());
+    self.realms.set_maintenance_display(config.maintenance_display);
+    self
+      .realms
+      .set_overflow(config.realm_overflow, config.vip_overflow_margin);
+    Ok(())
+  }
+
+  /// Changes the state of a realm, until it re-registers or the state of its static definition
+  /// changes.
+  pub fn set_realm_state(&self, id: RealmServerId, state: RealmState) -> Result<()> {
+    let realm = self
+      .realms
+      .set_state(id, state)
+      .with_context(|_| format!("Failed to change the state of realm {}", id))?;
+    info!("Realm updated: {}", realm);
+    Ok(())
+  }
+
   /// Returns whether the server is still active or not.
   pub fn is_active(&self) -> bool {
     self.connect_service.is_active() && self.rpc_service.is_active()
@@ -69,4 +140,13 @@ impl ConnectServer {
       .context("RPC service failure (stop)");
     connect_result.and(rpc_result).map_err(From::from)
   }
+
+  /// Validates a configuration against the registered extensions, logging any warnings.
+  pub fn check_config(config: &ConnectConfig, extensions: &ConnectExtensions) -> Result<()> {
+    let report = config.validate_with(extensions);
+    for issue in report.warnings() {
+      warn!("Configuration — {}", issue);
+    }
+    report.into_result().map_err(From::from)
+  }
 }
@@ -1,66 +1,327 @@
 pub use self::config::ConnectServiceConfig;
-pub use self::error::ConnectServiceError;
-use crate::util::{CloseSignal, ThreadController};
-use crate::{state::RealmServerList, Result};
+pub use self::error::{ClientError, ConnectServiceError, ServerError};
+pub use self::extensions::ConnectExtensions;
+pub use self::layout::{ClientRequest, LegacyLayout, PacketLayout, StandardLayout};
+pub use self::layout::{LEGACY_LAYOUT, STANDARD_LAYOUT};
+pub use self::net::{ExtensionFallback, PacketMiddleware, PacketResponder, ProtocolState};
+pub use self::net::{RealmFilter, WelcomePacket, XorKey};
+pub use self::error::ClientSessionError;
+pub use self::plugin::{load_bans, AccessRules, BanPolicy, ClientEventPlugin, StrikeWeights};
+#[cfg(feature = "scripting")]
+pub use self::script::ScriptHooks;
+pub use self::script::ScriptPaths;
+pub use self::version::{
+  ClientVersion, PatchServer, VersionAction, VersionPolicy, VersionRange, VersionRule,
+};
+use crate::util::{CloseSignal, IpNetwork, ThreadController};
+use crate::state::{ExtensionList, RealmServerList};
+use crate::Result;
+use failure::{format_err, ResultExt};
+use log::warn;
+use parking_lot::RwLock;
 use std::sync::Arc;
 
 mod config;
 mod error;
+mod extensions;
+mod layout;
 mod net;
 mod plugin;
+mod script;
+mod version;
 
 /// A connect service instance.
-pub struct ConnectService(ThreadController);
+pub struct ConnectService {
+  ctl: ThreadController,
+  settings: Vec<ServiceSettings>,
+  shared: SharedPlugins,
+}
 
 impl ConnectService {
-  /// Spawns a new Connect Service instance.
-  pub fn spawn(config: Arc<impl ConnectServiceConfig>, realms: RealmServerList) -> Self {
-    let ctl = ThreadController::spawn(move |rx| Self::serve(&*config, realms, rx));
-    ConnectService(ctl)
+  /// Spawns a new Connect Service instance, with one or more client listeners.
+  ///
+  /// Any bans and the total client capacity are shared between all listeners, whilst each
+  /// listener's own capacity is an additional limit. Each listener's packet layout must be either
+  /// built-in or registered as an extension. Packets handled by an extension service registered
+  /// over RPC are forwarded to it on every listener, unless a listener's layout or a custom
+  /// responder already answers them.
+  pub fn spawn(
+    listeners: Vec<impl ConnectServiceConfig>,
+    bans: BanPolicy,
+    max_connections: usize,
+    extensions: &ConnectExtensions,
+    realms: RealmServerList,
+    extension_services: ExtensionList,
+  ) -> Result<Self> {
+    for name in extensions.plugin_listeners() {
+      if !listeners.iter().any(|config| config.name() == name) {
+        Err(format_err!("Plugin registered for an unknown listener '{}'", name))?;
+      }
+    }
+
+    let protocols = listeners
+      .iter()
+      .map(|config| ListenerProtocol::resolve(config, extensions))
+      .collect::<Result<Vec<_>>>()?;
+    for protocol in &protocols {
+      extension_services.reserve(protocol.layout.codes());
+      extension_services.reserve(protocol.responders.codes());
+    }
+
+    let settings = listeners
+      .iter()
+      .map(ServiceSettings::new)
+      .collect::<Vec<_>>();
+    let shared = SharedPlugins {
+      bans: Arc::new(plugin::CheckClientBans::new(bans)),
+      max_clients: Arc::new(plugin::CheckMaximumClients::new(max_connections)),
+    };
+    let ctl = ThreadController::spawn(closet!([settings, shared] move |rx| {
+      Self::serve(listeners, settings, protocols, shared, realms, extension_services, rx)
+    }));
+    Ok(ConnectService {
+      ctl,
+      settings,
+      shared,
+    })
+  }
+
+  /// Applies the session options, accepted versions, client capacities, connection rates, access
+  /// lists, staff and VIP networks of each listener, along with the ban policy and total client
+  /// capacity.
+  ///
+  /// Only new client sessions are affected. Listeners are matched by name, and any changes to
+  /// the listeners themselves, their addresses, plugins, packet layouts, XOR keys, prefix lengths
+  /// or maximum packet size require the service to be restarted.
+  pub fn reload(
+    &self,
+    listeners: &[impl ConnectServiceConfig],
+    bans: BanPolicy,
+    max_connections: usize,
+  ) {
+    self.shared.bans.set_policy(bans);
+    self.shared.max_clients.set_capacity(max_connections);
+    for config in listeners {
+      match self.settings.iter().find(|settings| settings.name == config.name()) {
+        Some(settings) => settings.apply(config),
+        None => warn!("Listener '{}' requires a restart", config.name()),
+      }
+    }
   }
 
   /// Returns whether the service is still active or not.
   pub fn is_active(&self) -> bool {
-    self.0.is_alive()
+    self.ctl.is_alive()
   }
 
   /// Stops the service.
   pub fn stop(self) -> Result<()> {
-    self.0.stop()
+    self.ctl.stop()
   }
 
   /// Will block, waiting for the service to finish.
   pub fn wait(self) -> Result<()> {
-    self.0.wait()
+    self.ctl.wait()
   }
 
   fn serve(
-    config: &impl ConnectServiceConfig,
+    listeners: Vec<impl ConnectServiceConfig>,
+    settings: Vec<ServiceSettings>,
+    protocols: Vec<ListenerProtocol>,
+    shared: SharedPlugins,
     realms: RealmServerList,
+    extension_services: ExtensionList,
     close_rx: CloseSignal,
   ) -> Result<()> {
-    // Maps incoming packets to server responses
-    let mut responder = net::ClientPacketResponder::new(realms);
-    responder.set_ignore_unknown_packets(config.ignore_unknown_packets());
-
-    // Factory for the packet codec
-    let max_packet_size = config.max_packet_size();
-    let codec_provider = move || net::codec(max_packet_size);
-
-    // Manages each client's stream
-    let mut client_handler = net::ClientStreamHandler::new(responder, codec_provider);
-    client_handler.set_max_idle_time(config.max_idle_time());
-    client_handler.set_max_requests(config.max_requests());
-    client_handler.set_max_unresponsive_time(config.max_unresponsive_time());
-    client_handler.register_plugin(plugin::ClientEventLogger);
-    client_handler.register_plugin(plugin::CheckMaximumClients::new(config.max_connections()));
-    client_handler.register_plugin(plugin::CheckMaximumClientsPerIp::new(
-      config.max_connections_per_ip(),
+    let mut listener = net::ClientListener::new(close_rx);
+    listener.register_plugin(plugin::ListenerEventLogger);
+
+    for ((config, settings), protocol) in listeners.iter().zip(settings).zip(protocols) {
+      let protocol = protocol.with_scripts(&settings);
+
+      // Maps incoming packets to server responses, preferring any custom responders
+      let middleware = protocol.responders.clone();
+      let responder = net::ResponderChain::new(
+        protocol.responders,
+        net::ClientPacketResponder::new(
+          realms.clone(),
+          protocol.layout.clone(),
+          settings.versions.clone(),
+          protocol.realm_filters,
+          settings.staff.clone(),
+          settings.vip.clone(),
+        ),
+      );
+
+      // Factory for the packet codec
+      let max_packet_size = config.max_packet_size();
+      let cipher = protocol.cipher;
+      let codec_provider = move || net::codec(max_packet_size, cipher);
+
+      // Manages each client's stream
+      let client_handler = net::ClientStreamHandler::new(
+        responder,
+        middleware,
+        codec_provider,
+        protocol.layout,
+        extension_services.clone(),
+        settings.session.clone(),
+      );
+      client_handler.register_plugin(settings.ip_access.clone());
+      client_handler.register_plugin(shared.bans.clone());
+      #[cfg(feature = "scripting")]
+      client_handler.register_plugin(settings.scripts.clone());
+      client_handler.register_plugin(settings.connection_rate.clone());
+      client_handler.register_plugin(plugin::ClientEventLogger);
+      client_handler.register_plugin(shared.max_clients.clone());
+      client_handler.register_plugin(settings.max_clients.clone());
+      if config.check_clients_per_ip() {
+        client_handler.register_plugin(settings.max_clients_per_ip.clone());
+      }
+      for plugin in protocol.plugins {
+        client_handler.register_plugin(plugin);
+      }
+
+      // Listen for incoming client connections
+      listener.bind(&config.sockets(), client_handler);
+    }
+
+    listener.listen().map_err(From::from)
+  }
+}
+
+/// Plugins shared by all listeners.
+#[derive(Clone)]
+struct SharedPlugins {
+  bans: Arc<plugin::CheckClientBans>,
+  max_clients: Arc<plugin::CheckMaximumClients>,
+}
+
+/// The packet layout, XOR key, custom responders, realm filters and plugins of a listener, fixed
+/// for the lifetime of the service.
+struct ListenerProtocol {
+  layout: Arc<dyn PacketLayout>,
+  cipher: Option<&'static [u8; 32]>,
+  responders: net::PacketResponders,
+  realm_filters: net::RealmFilters,
+  plugins: Vec<Arc<dyn ClientEventPlugin>>,
+}
+
+impl ListenerProtocol {
+  fn resolve(config: &impl ConnectServiceConfig, extensions: &ConnectExtensions) -> Result<Self> {
+    let layout = extensions.layouts.get(config.packet_layout()).ok_or_else(|| {
+      format_err!(
+        "Listener '{}' uses an unknown packet layout '{}'",
+        config.name(),
+        config.packet_layout()
+      )
+    })?;
+
+    let key = match (config.xor_key(), config.xor_key_file()) {
+      (Some(key), _) => Some(key),
+      (None, Some(path)) => Some(
+        XorKey::load(&path)
+          .with_context(|_| format!("Listener '{}' has an invalid XOR key", config.name()))?,
+      ),
+      (None, None) => None,
+    };
+
+    Ok(ListenerProtocol {
+      layout,
+      cipher: key.map(XorKey::leak),
+      responders: extensions.responders.clone(),
+      realm_filters: extensions.realm_filters.clone(),
+      plugins: extensions.plugins_for(config.name()),
+    })
+  }
+
+  /// Applies the packet and realm list scripts of a listener.
+  #[cfg(feature = "scripting")]
+  fn with_scripts(mut self, settings: &ServiceSettings) -> Self {
+    self.responders.register_middleware(script::ScriptPacketFilter::new(
+      settings.scripts.clone(),
+      self.layout.clone(),
     ));
+    self.realm_filters.register(settings.scripts.clone());
+    self
+  }
 
-    // Listen for incoming client connections
-    let listener = net::ClientListener::new(config.socket(), close_rx);
-    listener.register_plugin(plugin::ListenerEventLogger);
-    listener.listen(client_handler).map_err(From::from)
+  #[cfg(not(feature = "scripting"))]
+  fn with_scripts(self, _settings: &ServiceSettings) -> Self {
+    self
+  }
+}
+
+/// Settings shared with a running service, allowing them to be reloaded.
+#[derive(Clone)]
+struct ServiceSettings {
+  name: String,
+  session: Arc<RwLock<net::SessionOptions>>,
+  versions: Arc<RwLock<VersionPolicy>>,
+  max_clients: Arc<plugin::CheckMaximumClients>,
+  max_clients_per_ip: Arc<plugin::CheckMaximumClientsPerIp>,
+  connection_rate: Arc<plugin::CheckConnectionRate>,
+  ip_access: Arc<plugin::CheckIpAccess>,
+  staff: Arc<RwLock<Vec<IpNetwork>>>,
+  vip: Arc<RwLock<Vec<IpNetwork>>>,
+  #[cfg(feature = "scripting")]
+  scripts: Arc<script::ScriptHooks>,
+}
+
+impl ServiceSettings {
+  fn new(config: &impl ConnectServiceConfig) -> Self {
+    ServiceSettings {
+      name: config.name().into(),
+      session: Arc::new(RwLock::new(Self::session_options(config))),
+      versions: Arc::new(RwLock::new(config.versions())),
+      max_clients: Arc::new(plugin::CheckMaximumClients::new(config.max_connections())),
+      max_clients_per_ip: Arc::new(plugin::CheckMaximumClientsPerIp::new(
+        config.max_connections_per_ip(),
+        config.ipv4_prefix_len(),
+        config.ipv6_prefix_len(),
+      )),
+      connection_rate: Arc::new(plugin::CheckConnectionRate::new(
+        config.connection_rate(),
+        config.connection_rate_per_ip(),
+      )),
+      ip_access: Arc::new(plugin::CheckIpAccess::new(config.access_list())),
+      staff: Arc::new(RwLock::new(config.staff())),
+      vip: Arc::new(RwLock::new(config.vip())),
+      #[cfg(feature = "scripting")]
+      scripts: Arc::new(script::ScriptHooks::new(&config.scripts())),
+    }
+  }
+
+  fn apply(&self, config: &impl ConnectServiceConfig) {
+    *self.session.write() = Self::session_options(config);
+    *self.versions.write() = config.versions();
+    self.max_clients.set_capacity(config.max_connections());
+    self
+      .max_clients_per_ip
+      .set_capacity_per_ip(config.max_connections_per_ip());
+    self
+      .connection_rate
+      .set_limits(config.connection_rate(), config.connection_rate_per_ip());
+    self.ip_access.set_path(config.access_list());
+    *self.staff.write() = config.staff();
+    *self.vip.write() = config.vip();
+    #[cfg(feature = "scripting")]
+    self.scripts.set_paths(&config.scripts());
+  }
+
+  fn session_options(config: &impl ConnectServiceConfig) -> net::SessionOptions {
+    net::SessionOptions {
+      max_idle_time: config.max_idle_time(),
+      max_requests: config.max_requests(),
+      request_rate: config.request_rate(),
+      realm_list_rate: config.realm_list_rate(),
+      realm_connect_rate: config.realm_connect_rate(),
+      max_unresponsive_time: config.max_unresponsive_time(),
+      ignore_unknown_packets: config.ignore_unknown_packets(),
+      require_handshake: config.require_handshake(),
+      welcome_packet: config.welcome_packet(),
+      extension_timeout: config.extension_timeout(),
+      extension_fallback: config.extension_fallback(),
+    }
   }
 }
@@ -1,10 +1,14 @@
-use std::net::{IpAddr, SocketAddr};
+use super::{ExtensionFallback, ScriptPaths, VersionPolicy, WelcomePacket, XorKey};
+use crate::util::{IpNetwork, RateLimit};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::time::Duration;
 
+/// The configuration of a client listener.
 pub trait ConnectServiceConfig: Send + Sync + 'static {
-  fn host(&self) -> IpAddr;
+  fn name(&self) -> &str;
 
-  fn port(&self) -> u16;
+  fn sockets(&self) -> Vec<SocketAddr>;
 
   fn max_idle_time(&self) -> Duration;
 
@@ -14,13 +18,49 @@ pub trait ConnectServiceConfig: Send + Sync + 'static {
 
   fn max_requests(&self) -> usize;
 
+  fn request_rate(&self) -> Option<RateLimit>;
+
+  fn realm_list_rate(&self) -> Option<RateLimit>;
+
+  fn realm_connect_rate(&self) -> Option<RateLimit>;
+
   fn max_connections(&self) -> usize;
 
   fn max_connections_per_ip(&self) -> usize;
 
+  fn connection_rate(&self) -> Option<RateLimit>;
+
+  fn connection_rate_per_ip(&self) -> Option<RateLimit>;
+
+  fn access_list(&self) -> Option<PathBuf>;
+
+  fn staff(&self) -> Vec<IpNetwork>;
+
+  fn vip(&self) -> Vec<IpNetwork>;
+
+  fn ipv4_prefix_len(&self) -> u8;
+
+  fn ipv6_prefix_len(&self) -> u8;
+
   fn ignore_unknown_packets(&self) -> bool;
 
-  fn socket(&self) -> SocketAddr {
-    SocketAddr::new(self.host(), self.port())
-  }
+  fn require_handshake(&self) -> bool;
+
+  fn welcome_packet(&self) -> Option<WelcomePacket>;
+
+  fn versions(&self) -> VersionPolicy;
+
+  fn packet_layout(&self) -> &str;
+
+  fn xor_key(&self) -> Option<XorKey>;
+
+  fn xor_key_file(&self) -> Option<PathBuf>;
+
+  fn extension_timeout(&self) -> Duration;
+
+  fn extension_fallback(&self) -> ExtensionFallback;
+
+  fn scripts(&self) -> ScriptPaths;
+
+  fn check_clients_per_ip(&self) -> bool;
 }
@@ -1,7 +1,8 @@
-use crate::state::RealmServerListError;
+use crate::state::{RealmServerId, RealmServerListError};
 use failure::Fail;
 use muonline_protocol::connect::Version;
 use std::io;
+use std::net::SocketAddr;
 use tokio::timer::timeout;
 
 #[derive(Fail, Debug)]
@@ -15,21 +16,32 @@ pub enum ClientError {
   #[fail(display = "Invalid packet received")]
   InvalidPacket(#[fail(cause)] io::Error),
 
+  #[fail(display = "Request received before the version handshake")]
+  HandshakeRequired,
+
   #[fail(display = "Maximum packet count exceeded")]
   MaxPacketsExceeded,
 
+  #[fail(display = "Version handshake repeated")]
+  RepeatedHandshake,
+
+  #[fail(display = "Realm {} hidden from the client", _0)]
+  RealmHidden(RealmServerId),
+
+  #[fail(display = "Realm {} refused", _0)]
+  RealmRefused(RealmServerId, #[fail(cause)] RealmServerListError),
+
+  #[fail(display = "Request rate exceeded")]
+  RequestRateExceeded,
+
   #[fail(display = "Session timed out")]
   TimedOut,
 
   #[fail(display = "Unknown packet received; {:?}", header)]
   UnknownPacket { header: Vec<u8> },
 
-  #[fail(
-    display = "Version mismatch; was {:?}, expected {:?}",
-    has,
-    expected
-  )]
-  VersionMismatch { has: Version, expected: Version },
+  #[fail(display = "Version mismatch; {:?} is not accepted", has)]
+  VersionMismatch { has: Version },
 }
 
 #[derive(Fail, Debug)]
@@ -49,8 +61,11 @@ pub enum ServerError {
   #[fail(display = "Connection stream failed")]
   Connection(#[fail(cause)] io::Error),
 
-  #[fail(display = "Failed to bind to port")]
-  Bind(#[fail(cause)] io::Error),
+  #[fail(display = "Extension '{}' failed to respond", _0)]
+  ExtensionFailure(String),
+
+  #[fail(display = "Failed to bind to {}", _0)]
+  Bind(SocketAddr, #[fail(cause)] io::Error),
 
   #[fail(display = "Invalid packet constructed")]
   InvalidPacket(#[fail(cause)] io::Error),
@@ -59,6 +74,13 @@ pub enum ServerError {
   RealmState(#[fail(cause)] RealmServerListError),
 }
 
+/// An error ending a client's session.
+#[derive(Debug)]
+pub struct ClientSessionError {
+  pub socket: SocketAddr,
+  pub error: ConnectServiceError,
+}
+
 #[derive(Fail, Debug)]
 pub enum ConnectServiceError {
   #[fail(display = "Server error")]
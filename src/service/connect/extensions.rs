@@ -0,0 +1,94 @@
+use super::layout::{PacketLayout, PacketLayouts};
+use super::net::{PacketMiddleware, PacketResponder, PacketResponders, RealmFilter, RealmFilters};
+use super::plugin::ClientEventPlugin;
+use std::sync::Arc;
+
+/// Extensions of the connect service, registered by library users.
+#[derive(Clone, Default)]
+pub struct ConnectExtensions {
+  pub(super) layouts: PacketLayouts,
+  pub(super) responders: PacketResponders,
+  pub(super) realm_filters: RealmFilters,
+  plugins: Vec<(Option<String>, Arc<dyn ClientEventPlugin>)>,
+}
+
+impl ConnectExtensions {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Registers a packet layout, selectable by name in the protocol profiles.
+  pub fn register_layout(&mut self, name: &str, layout: impl PacketLayout) -> &mut Self {
+    self.layouts.register(name, layout);
+    self
+  }
+
+  /// Returns whether a packet layout is available, either built-in or registered.
+  pub fn has_layout(&self, name: &str) -> bool {
+    self.layouts.contains(name)
+  }
+
+  /// Registers a responder for a packet kind (e.g `0xC1`) and code, answering those packets
+  /// before the built-in responder on every listener.
+  ///
+  /// Custom responders are responsible for checking the protocol state, such as whether the
+  /// handshake is required (see `ProtocolState::check_request`). Extension services may not
+  /// register the same packets over RPC.
+  pub fn register_responder(
+    &mut self,
+    kind: u8,
+    code: u8,
+    responder: impl PacketResponder,
+  ) -> &mut Self {
+    self.responders.register(kind, code, responder);
+    self
+  }
+
+  /// Registers middleware, inspecting, rewriting or vetoing packets before and after they are
+  /// answered or forwarded to an extension service on every listener.
+  pub fn register_middleware(&mut self, middleware: impl PacketMiddleware) -> &mut Self {
+    self.responders.register_middleware(middleware);
+    self
+  }
+
+  /// Registers a filter, hiding any realms it rejects from the realm list on every listener, and
+  /// refusing clients requesting to join them.
+  pub fn register_realm_filter(&mut self, filter: impl RealmFilter) -> &mut Self {
+    self.realm_filters.register(filter);
+    self
+  }
+
+  /// Registers a client plugin on every listener, invoked after the built-in plugins.
+  pub fn register_plugin(&mut self, plugin: impl ClientEventPlugin) -> &mut Self {
+    self.plugins.push((None, Arc::new(plugin)));
+    self
+  }
+
+  /// Registers a client plugin on a single listener, invoked after the built-in plugins.
+  pub fn register_listener_plugin(
+    &mut self,
+    listener: &str,
+    plugin: impl ClientEventPlugin,
+  ) -> &mut Self {
+    self.plugins.push((Some(listener.into()), Arc::new(plugin)));
+    self
+  }
+
+  /// Returns the names of any listeners with their own plugins.
+  pub(super) fn plugin_listeners(&self) -> impl Iterator<Item = &str> {
+    self
+      .plugins
+      .iter()
+      .filter_map(|(listener, _)| listener.as_ref().map(String::as_str))
+  }
+
+  /// Returns the plugins of a listener, including those of every listener.
+  pub(super) fn plugins_for(&self, listener: &str) -> Vec<Arc<dyn ClientEventPlugin>> {
+    self
+      .plugins
+      .iter()
+      .filter(|(name, _)| name.as_ref().map_or(true, |name| name == listener))
+      .map(|(_, plugin)| plugin.clone())
+      .collect()
+  }
+}
@@ -0,0 +1,238 @@
+use super::net::raw_packet;
+use crate::state::{PacketCode, RealmServer, RealmServerId};
+use muonline_packet::{Packet, PacketEncodable};
+use muonline_protocol::connect::{server, Client, Version};
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+
+/// The name of the layout used by `muonline_protocol`.
+pub const STANDARD_LAYOUT: &str = "standard";
+
+/// The name of the layout used by clients predating two byte realm IDs.
+pub const LEGACY_LAYOUT: &str = "legacy";
+
+/// A request from a client, independent of its packet layout.
+#[derive(Debug)]
+pub enum ClientRequest {
+  /// The version handshake.
+  Handshake(Version),
+  /// A request for the realm server list.
+  RealmList,
+  /// A request for the address of a realm server.
+  RealmConnect(RealmServerId),
+  /// An unrecognized request.
+  Unknown,
+}
+
+/// The packet layout of a client generation, decoding requests and encoding responses.
+pub trait PacketLayout: Send + Sync + 'static {
+  /// Returns the packet kinds and codes of the requests decoded by the layout, which extension
+  /// services may therefore not handle.
+  fn codes(&self) -> Vec<PacketCode>;
+
+  /// Decodes a client packet, failing if a recognized packet is malformed.
+  fn decode(&self, packet: &Packet) -> io::Result<ClientRequest>;
+
+  /// Encodes the result of a successful version handshake.
+  fn handshake_result(&self) -> io::Result<Packet>;
+
+  /// Encodes the realm server list.
+  fn realm_list(&self, realms: &[RealmServer]) -> io::Result<Packet>;
+
+  /// Encodes the address of a realm server.
+  fn realm_connect(&self, realm: &RealmServer) -> io::Result<Packet>;
+}
+
+/// The layout of `muonline_protocol`.
+pub struct StandardLayout;
+
+impl PacketLayout for StandardLayout {
+  fn codes(&self) -> Vec<PacketCode> {
+    // The version handshake, followed by the realm list and connect requests
+    vec![(0xC1, 0x05), (0xC1, 0xF4)]
+  }
+
+  fn decode(&self, packet: &Packet) -> io::Result<ClientRequest> {
+    Ok(match Client::from_packet(packet)? {
+      Client::ConnectServerRequest(request) => ClientRequest::Handshake(request.version),
+      Client::RealmServerListRequest => ClientRequest::RealmList,
+      Client::RealmServerConnectRequest(server) => ClientRequest::RealmConnect(server.id),
+      _ => ClientRequest::Unknown,
+    })
+  }
+
+  fn handshake_result(&self) -> io::Result<Packet> {
+    server::ConnectServerResult::success().to_packet()
+  }
+
+  fn realm_list(&self, realms: &[RealmServer]) -> io::Result<Packet> {
+    let list = realms
+      .iter()
+      .map(|realm| (realm.id, realm.load_factor().into()).into())
+      .collect();
+    server::RealmServerList(list).to_packet()
+  }
+
+  fn realm_connect(&self, realm: &RealmServer) -> io::Result<Packet> {
+    server::RealmServerConnect::new(realm.host.clone(), realm.port).to_packet()
+  }
+}
+
+/// The layout of clients predating two byte realm IDs.
+///
+/// The realm list is requested as `C1 04 F4 02`, and answered as `C2 size F4 02 count`, followed
+/// by the ID and load percentage of each realm as single bytes. Realms are joined by `C1 05 F4 03
+/// id`, whilst the handshake and realm address are encoded as in the standard layout. Realms with
+/// an ID above 255 can't be listed, and are therefore omitted.
+pub struct LegacyLayout;
+
+impl PacketLayout for LegacyLayout {
+  fn codes(&self) -> Vec<PacketCode> {
+    vec![(0xC1, 0xF4)]
+  }
+
+  fn decode(&self, packet: &Packet) -> io::Result<ClientRequest> {
+    if (packet.kind() as u8, packet.code()) != (0xC1, 0xF4) {
+      return Ok(ClientRequest::Unknown);
+    }
+
+    let data = packet.data();
+    match (data.first().cloned(), data.len()) {
+      (Some(0x02), 1) => Ok(ClientRequest::RealmList),
+      (Some(0x03), 2) => Ok(ClientRequest::RealmConnect(RealmServerId::from(data[1]))),
+      (Some(0x02), _) | (Some(0x03), _) => Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "malformed realm request",
+      )),
+      _ => Ok(ClientRequest::Unknown),
+    }
+  }
+
+  fn handshake_result(&self) -> io::Result<Packet> {
+    StandardLayout.handshake_result()
+  }
+
+  fn realm_list(&self, realms: &[RealmServer]) -> io::Result<Packet> {
+    let realms = realms
+      .iter()
+      .filter(|realm| realm.id <= RealmServerId::from(u8::max_value()))
+      .take(usize::from(u8::max_value()))
+      .collect::<Vec<_>>();
+
+    let mut bytes = vec![0xC2, 0x00, 0x00, 0xF4, 0x02, realms.len() as u8];
+    for realm in realms {
+      bytes.extend_from_slice(&[realm.id as u8, load_percentage(realm)]);
+    }
+
+    let size = bytes.len();
+    bytes[1] = (size >> 8) as u8;
+    bytes[2] = size as u8;
+    raw_packet(&bytes)
+  }
+
+  fn realm_connect(&self, realm: &RealmServer) -> io::Result<Packet> {
+    StandardLayout.realm_connect(realm)
+  }
+}
+
+/// Returns the load of a realm as a percentage, treating a realm without capacity as full.
+fn load_percentage(realm: &RealmServer) -> u8 {
+  if realm.capacity == 0 {
+    100
+  } else {
+    (realm.clients.saturating_mul(100) / realm.capacity).min(100) as u8
+  }
+}
+
+/// Packet layouts available to the listeners, by name.
+#[derive(Clone)]
+pub struct PacketLayouts {
+  layouts: HashMap<String, Arc<dyn PacketLayout>>,
+}
+
+impl Default for PacketLayouts {
+  fn default() -> Self {
+    let mut layouts = PacketLayouts {
+      layouts: HashMap::new(),
+    };
+    layouts.register(STANDARD_LAYOUT, StandardLayout);
+    layouts.register(LEGACY_LAYOUT, LegacyLayout);
+    layouts
+  }
+}
+
+impl PacketLayouts {
+  /// Registers a layout, replacing any existing one with the same name.
+  pub fn register(&mut self, name: &str, layout: impl PacketLayout) {
+    self.layouts.insert(name.into(), Arc::new(layout));
+  }
+
+  /// Returns whether a layout is registered or not.
+  pub fn contains(&self, name: &str) -> bool {
+    self.layouts.contains_key(name)
+  }
+
+  /// Returns a layout by its name.
+  pub fn get(&self, name: &str) -> Option<Arc<dyn PacketLayout>> {
+    self.layouts.get(name).cloned()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::service::connect::net::raw_bytes;
+  use crate::state::{RealmOrigin, RealmState};
+
+  fn realm(id: RealmServerId, clients: usize, capacity: usize) -> RealmServer {
+    RealmServer {
+      id,
+      host: "127.0.0.1".into(),
+      port: 55901,
+      clients,
+      capacity,
+      origin: RealmOrigin::Static,
+      state: RealmState::Online,
+    }
+  }
+
+  fn decode(bytes: &[u8]) -> io::Result<ClientRequest> {
+    LegacyLayout.decode(&raw_packet(bytes).unwrap())
+  }
+
+  #[test]
+  fn decodes_legacy_requests() {
+    assert!(matches!(decode(&[0xC1, 0x04, 0xF4, 0x02]), Ok(ClientRequest::RealmList)));
+    assert!(matches!(
+      decode(&[0xC1, 0x05, 0xF4, 0x03, 0x15]),
+      Ok(ClientRequest::RealmConnect(0x15))
+    ));
+    assert!(matches!(decode(&[0xC1, 0x04, 0xF4, 0x06]), Ok(ClientRequest::Unknown)));
+    assert!(matches!(decode(&[0xC1, 0x04, 0x05, 0x01]), Ok(ClientRequest::Unknown)));
+  }
+
+  #[test]
+  fn rejects_malformed_legacy_requests() {
+    assert!(decode(&[0xC1, 0x05, 0xF4, 0x02, 0x00]).is_err());
+    assert!(decode(&[0xC1, 0x04, 0xF4, 0x03]).is_err());
+    assert!(decode(&[0xC1, 0x06, 0xF4, 0x03, 0x00, 0x01]).is_err());
+  }
+
+  #[test]
+  fn encodes_legacy_realm_list() {
+    let realms = [realm(0, 50, 100), realm(21, 10, 0), realm(300, 0, 100)];
+    let packet = LegacyLayout.realm_list(&realms).unwrap();
+    assert_eq!(
+      raw_bytes(packet).unwrap(),
+      vec![0xC2, 0x00, 0x0A, 0xF4, 0x02, 0x02, 0x00, 50, 0x15, 100]
+    );
+  }
+
+  #[test]
+  fn registers_built_in_layouts() {
+    let layouts = PacketLayouts::default();
+    assert!(layouts.contains(STANDARD_LAYOUT));
+    assert!(layouts.contains(LEGACY_LAYOUT));
+  }
+}
@@ -1,16 +1,31 @@
 use auto_impl::auto_impl;
+use bytes::BytesMut;
 use crate::service::connect::error::{ConnectServiceError, Result};
+use crate::state::RealmServer;
+use failure::format_err;
 use futures::Future;
 use muonline_packet::{Packet, PacketCodec, PacketCodecState, XOR_CIPHER};
+use std::{io, net::SocketAddr};
+use tokio::codec::{Decoder, Encoder};
 use tokio::net::TcpStream;
 
-pub use self::handler::ClientStreamHandler;
+pub use self::chain::{PacketResponders, RealmFilters, ResponderChain};
+pub use self::cipher::XorKey;
+pub use self::forward::ExtensionFallback;
+pub use self::handler::{ClientStreamHandler, SessionOptions};
 pub use self::listener::ClientListener;
 pub use self::responder::ClientPacketResponder;
+pub use self::session::ProtocolState;
+pub use self::welcome::WelcomePacket;
 
+mod chain;
+mod cipher;
+mod forward;
 mod handler;
 mod listener;
 mod responder;
+mod session;
+mod welcome;
 
 /// Represents a boxed connect service future.
 type ConnectServiceFuture<T> = Box<Future<Item = T, Error = ConnectServiceError> + Send + 'static>;
@@ -23,8 +38,47 @@ pub trait StreamHandler: Send + Sync + 'static {
 
 #[auto_impl(Fn)]
 pub trait PacketResponder: Send + Sync + 'static {
-  /// Constructs a response for a client packet.
-  fn respond(&self, packet: &Packet) -> Result<Option<Packet>>;
+  /// Constructs a response for a client packet, updating the session's protocol state.
+  fn respond(
+    &self,
+    client: &SocketAddr,
+    state: &mut ProtocolState,
+    packet: &Packet,
+  ) -> Result<Option<Packet>>;
+}
+
+/// Middleware inspecting client packets and their responses.
+///
+/// Returning `None` vetoes a packet, leaving it unanswered, whilst an error ends the session.
+#[auto_impl(Arc)]
+pub trait PacketMiddleware: Send + Sync + 'static {
+  /// Invoked before a packet is answered, optionally rewriting it.
+  fn before(
+    &self,
+    _client: &SocketAddr,
+    _state: &ProtocolState,
+    request: Packet,
+  ) -> Result<Option<Packet>> {
+    Ok(Some(request))
+  }
+
+  /// Invoked before a response is sent, optionally rewriting it.
+  fn after(
+    &self,
+    _client: &SocketAddr,
+    _state: &ProtocolState,
+    _request: &Packet,
+    response: Packet,
+  ) -> Result<Option<Packet>> {
+    Ok(Some(response))
+  }
+}
+
+#[auto_impl(Fn, Arc)]
+pub trait RealmFilter: Send + Sync + 'static {
+  /// Returns whether a realm is included in the realm list sent to a client, and may be joined by
+  /// it.
+  fn is_visible(&self, client: &SocketAddr, realm: &RealmServer) -> bool;
 }
 
 #[auto_impl(Fn)]
@@ -33,11 +87,68 @@ pub trait PacketCodecProvider: Send + Sync + 'static {
   fn create(&self) -> PacketCodec;
 }
 
-/// Returns the codec used for a Connect Server.
-pub fn codec(max_size: usize) -> PacketCodec {
+/// Returns the codec used for a Connect Server, decrypting with the stock XOR key by default.
+pub fn codec(max_size: usize, cipher: Option<&'static [u8; 32]>) -> PacketCodec {
   PacketCodec::with_max_size(
     PacketCodecState::new(),
-    PacketCodecState::builder().cipher(&XOR_CIPHER).build(),
+    PacketCodecState::builder()
+      .cipher(cipher.unwrap_or(&XOR_CIPHER))
+      .build(),
     max_size,
   )
 }
+
+/// Decodes a single unencrypted packet from its raw bytes.
+pub fn raw_packet(bytes: &[u8]) -> io::Result<Packet> {
+  let mut codec = PacketCodec::with_max_size(
+    PacketCodecState::new(),
+    PacketCodecState::new(),
+    bytes.len(),
+  );
+  let mut buffer = BytesMut::from(bytes);
+
+  match codec.decode(&mut buffer)? {
+    Some(packet) => if buffer.is_empty() {
+      Ok(packet)
+    } else {
+      Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "trailing bytes after the packet",
+      ))
+    },
+    None => Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      "incomplete packet",
+    )),
+  }
+}
+
+/// Encodes a packet as its raw, unencrypted bytes.
+pub fn raw_bytes(packet: Packet) -> io::Result<Vec<u8>> {
+  let mut codec = PacketCodec::with_max_size(
+    PacketCodecState::new(),
+    PacketCodecState::new(),
+    usize::from(u16::max_value()),
+  );
+  let mut buffer = BytesMut::new();
+  codec.encode(packet, &mut buffer)?;
+  Ok(buffer.to_vec())
+}
+
+/// Parses a sequence of hex bytes, ignoring any whitespace (e.g `C1 04 00 01`).
+pub fn hex_bytes(value: &str) -> std::result::Result<Vec<u8>, failure::Error> {
+  let digits = value
+    .chars()
+    .filter(|digit| !digit.is_whitespace())
+    .collect::<Vec<_>>();
+  if digits.is_empty() || digits.len() % 2 != 0 {
+    Err(format_err!("Expected hex bytes such as 'C1 04 00 01'"))?;
+  }
+
+  digits
+    .chunks(2)
+    .map(|pair| {
+      let pair = pair.iter().collect::<String>();
+      u8::from_str_radix(&pair, 16).map_err(|_| format_err!("Invalid hex byte '{}'", pair))
+    }).collect()
+}
@@ -0,0 +1,119 @@
+use super::{PacketMiddleware, PacketResponder, ProtocolState, RealmFilter};
+use crate::service::connect::error::Result;
+use crate::state::RealmServer;
+use muonline_packet::Packet;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Custom responders and middleware, shared by all listeners.
+#[derive(Clone, Default)]
+pub struct PacketResponders {
+  responders: HashMap<(u8, u8), Arc<dyn PacketResponder>>,
+  middleware: Vec<Arc<dyn PacketMiddleware>>,
+}
+
+impl PacketResponders {
+  /// Registers a responder for a packet kind (e.g `0xC1`) and code, replacing any existing one.
+  pub fn register(&mut self, kind: u8, code: u8, responder: impl PacketResponder) {
+    self.responders.insert((kind, code), Arc::new(responder));
+  }
+
+  /// Appends middleware, invoked in the order of registration.
+  pub fn register_middleware(&mut self, middleware: impl PacketMiddleware) {
+    self.middleware.push(Arc::new(middleware));
+  }
+
+  /// Returns the packet kinds and codes answered by custom responders.
+  pub fn codes(&self) -> impl Iterator<Item = (u8, u8)> + '_ {
+    self.responders.keys().cloned()
+  }
+
+  /// Passes a request through the middleware, returning `None` if it is vetoed.
+  pub fn before(
+    &self,
+    client: &SocketAddr,
+    state: &ProtocolState,
+    mut request: Packet,
+  ) -> Result<Option<Packet>> {
+    for middleware in &self.middleware {
+      request = match middleware.before(client, state, request)? {
+        Some(request) => request,
+        None => return Ok(None),
+      };
+    }
+    Ok(Some(request))
+  }
+
+  /// Passes the response of a request through the middleware, returning `None` if it is vetoed.
+  pub fn after(
+    &self,
+    client: &SocketAddr,
+    state: &ProtocolState,
+    request: &Packet,
+    mut response: Packet,
+  ) -> Result<Option<Packet>> {
+    for middleware in &self.middleware {
+      response = match middleware.after(client, state, request, response)? {
+        Some(response) => response,
+        None => return Ok(None),
+      };
+    }
+    Ok(Some(response))
+  }
+
+  /// Returns the responder of a packet, if one is registered.
+  fn get(&self, packet: &Packet) -> Option<&Arc<dyn PacketResponder>> {
+    self.responders.get(&(packet.kind() as u8, packet.code()))
+  }
+}
+
+/// A responder answering packets with either a custom responder or the default one.
+///
+/// Any middleware is applied by the stream handler, so it also sees packets forwarded to extension
+/// services.
+pub struct ResponderChain<R: PacketResponder> {
+  custom: PacketResponders,
+  default: R,
+}
+
+impl<R: PacketResponder> ResponderChain<R> {
+  pub fn new(custom: PacketResponders, default: R) -> Self {
+    ResponderChain { custom, default }
+  }
+}
+
+impl<R: PacketResponder> PacketResponder for ResponderChain<R> {
+  fn respond(
+    &self,
+    client: &SocketAddr,
+    state: &mut ProtocolState,
+    packet: &Packet,
+  ) -> Result<Option<Packet>> {
+    match self.custom.get(packet) {
+      Some(responder) => responder.respond(client, state, packet),
+      None => self.default.respond(client, state, packet),
+    }
+  }
+}
+
+/// Filters applied to the realm list, hiding any realm rejected by one of them.
+#[derive(Clone, Default)]
+pub struct RealmFilters {
+  filters: Vec<Arc<dyn RealmFilter>>,
+}
+
+impl RealmFilters {
+  /// Appends a filter.
+  pub fn register(&mut self, filter: impl RealmFilter) {
+    self.filters.push(Arc::new(filter));
+  }
+
+  /// Returns whether a realm is visible to a client.
+  pub fn is_visible(&self, client: &SocketAddr, realm: &RealmServer) -> bool {
+    self
+      .filters
+      .iter()
+      .all(|filter| filter.is_visible(client, realm))
+  }
+}
@@ -0,0 +1,101 @@
+use super::hex_bytes;
+use crate::Result;
+use failure::{format_err, ResultExt};
+use serde::{de, Deserialize, Deserializer};
+use std::{fs, path::Path, str::FromStr};
+
+/// A XOR key, encrypting the client's packets to the connect server.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct XorKey(pub [u8; 32]);
+
+impl XorKey {
+  /// Loads a key from a file, either as 32 raw bytes or as hex text.
+  pub fn load(path: &Path) -> Result<Self> {
+    let content =
+      fs::read(path).with_context(|_| format!("Failed to read XOR key {}", path.display()))?;
+
+    if content.len() == 32 {
+      let mut key = [0; 32];
+      key.copy_from_slice(&content);
+      return Ok(XorKey(key));
+    }
+
+    let key = String::from_utf8(content)
+      .map_err(|_| format_err!("Expected 32 raw bytes or hex text"))
+      .and_then(|content| content.parse())
+      .with_context(|_| format!("Invalid XOR key {}", path.display()))?;
+    Ok(key)
+  }
+
+  /// Returns the key with a static lifetime, as required by the codec.
+  ///
+  /// The key is leaked, and should therefore only be created once per listener.
+  pub fn leak(self) -> &'static [u8; 32] {
+    Box::leak(Box::new(self.0))
+  }
+}
+
+impl std::fmt::Debug for XorKey {
+  fn fmt(&self, output: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(output, "XorKey(..)")
+  }
+}
+
+impl FromStr for XorKey {
+  type Err = failure::Error;
+
+  /// Parses 32 hex bytes, ignoring any whitespace.
+  fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+    let bytes = hex_bytes(value)?;
+    if bytes.len() != 32 {
+      Err(format_err!("Expected 32 hex bytes, found {}", bytes.len()))?;
+    }
+
+    let mut key = [0; 32];
+    key.copy_from_slice(&bytes);
+    Ok(XorKey(key))
+  }
+}
+
+impl<'de> Deserialize<'de> for XorKey {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+    String::deserialize(deserializer)?
+      .parse()
+      .map_err(de::Error::custom)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const KEY: &str = "AB CD EF 00 11 22 33 44 55 66 77 88 99 AA BB CC \
+                     DD EE FF 01 02 03 04 05 06 07 08 09 0A 0B 0C 0D";
+
+  #[test]
+  fn parses_hex_keys() {
+    let key: XorKey = KEY.parse().unwrap();
+    assert_eq!(key.0[..3], [0xAB, 0xCD, 0xEF]);
+    assert_eq!(key.0[31], 0x0D);
+
+    let compact = KEY.split_whitespace().collect::<String>().to_lowercase();
+    assert_eq!(compact.parse::<XorKey>().unwrap(), key);
+  }
+
+  #[test]
+  fn rejects_keys_of_other_lengths() {
+    let short = &KEY[..KEY.len() - 3];
+    let long = format!("{} 0E", KEY);
+    assert!(short.parse::<XorKey>().is_err());
+    assert!(long.parse::<XorKey>().is_err());
+    assert!("".parse::<XorKey>().is_err());
+  }
+
+  #[test]
+  fn rejects_malformed_keys() {
+    let invalid = KEY.replace("0D", "0Z");
+    let odd = &KEY[..KEY.len() - 1];
+    assert!(invalid.parse::<XorKey>().is_err());
+    assert!(odd.parse::<XorKey>().is_err());
+  }
+}
@@ -0,0 +1,109 @@
+use super::{hex_bytes, raw_bytes, raw_packet};
+use crate::service::connect::error::{ConnectServiceError, Result, ServerError};
+use crate::state::Extension;
+use failure::{format_err, Error};
+use futures::future::{self, Either};
+use futures::Future;
+use log::warn;
+use muonline_packet::Packet;
+use serde::{de, Deserialize, Deserializer};
+use std::net::SocketAddr;
+use std::{io, str::FromStr, sync::Arc, time::Duration};
+use tokio::prelude::FutureExt;
+
+/// The handling of a forwarded packet, when its extension service fails to respond in time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtensionFallback {
+  /// The packet is left unanswered.
+  Ignore,
+  /// The client is disconnected.
+  Disconnect,
+  /// The client is answered with a packet of raw bytes (e.g `C1 04 00 01`).
+  Packet(Vec<u8>),
+}
+
+impl ExtensionFallback {
+  /// Decodes the fallback packet, if any.
+  pub fn to_packet(&self) -> Option<io::Result<Packet>> {
+    match self {
+      ExtensionFallback::Packet(bytes) => Some(raw_packet(bytes)),
+      _ => None,
+    }
+  }
+
+  /// Returns the response of the fallback, if any.
+  fn respond(&self, extension: &str) -> Result<Option<Packet>> {
+    match self {
+      ExtensionFallback::Ignore => Ok(None),
+      ExtensionFallback::Disconnect => Err(ServerError::ExtensionFailure(extension.into()).into()),
+      ExtensionFallback::Packet(bytes) => raw_packet(bytes)
+        .map(Some)
+        .map_err(|error| ServerError::InvalidPacket(error).into()),
+    }
+  }
+}
+
+impl Default for ExtensionFallback {
+  fn default() -> Self {
+    ExtensionFallback::Ignore
+  }
+}
+
+impl FromStr for ExtensionFallback {
+  type Err = Error;
+
+  /// Parses either `ignore`, `disconnect` or a sequence of hex bytes.
+  fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+    match value.trim() {
+      "ignore" => Ok(ExtensionFallback::Ignore),
+      "disconnect" => Ok(ExtensionFallback::Disconnect),
+      value => hex_bytes(value)
+        .map(ExtensionFallback::Packet)
+        .map_err(|_| format_err!("Expected 'ignore', 'disconnect' or hex bytes")),
+    }
+  }
+}
+
+impl<'de> Deserialize<'de> for ExtensionFallback {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+    String::deserialize(deserializer)?
+      .parse()
+      .map_err(de::Error::custom)
+  }
+}
+
+/// Forwards a client packet to an extension service, resolving to its response.
+///
+/// The fallback applies if the extension times out, deregisters or responds with an invalid
+/// packet.
+pub fn forward(
+  extension: &Arc<Extension>,
+  client: SocketAddr,
+  packet: Packet,
+  timeout: Duration,
+  fallback: ExtensionFallback,
+) -> impl Future<Item = Option<Packet>, Error = ConnectServiceError> {
+  let bytes = match raw_bytes(packet) {
+    Ok(bytes) => bytes,
+    Err(error) => return Either::A(future::err(ServerError::InvalidPacket(error).into())),
+  };
+
+  let name = extension.name.clone();
+  let response = Extension::forward(extension, client, bytes)
+    .timeout(timeout)
+    .then(move |result| {
+      let reason = match result {
+        Ok(ref bytes) if bytes.is_empty() => return Ok(None),
+        Ok(bytes) => match raw_packet(&bytes) {
+          Ok(packet) => return Ok(Some(packet)),
+          Err(error) => format!("invalid response packet; {}", error),
+        },
+        Err(ref error) if error.is_elapsed() => "timed out".to_string(),
+        Err(_) => "deregistered".to_string(),
+      };
+
+      warn!("Extension '{}' failed to answer {}; {}", name, client, reason);
+      fallback.respond(&name)
+    });
+  Either::B(response)
+}
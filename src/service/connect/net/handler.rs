@@ -1,26 +1,73 @@
-use super::{ConnectServiceFuture, PacketCodecProvider};
-use super::{PacketResponder, StreamHandler};
+use super::{forward, ConnectServiceFuture, ExtensionFallback, PacketCodecProvider};
+use super::{PacketResponder, PacketResponders, ProtocolState, StreamHandler, WelcomePacket};
 use boolinator::Boolinator;
 use crate::service::connect::error::*;
+use crate::service::connect::layout::{ClientRequest, PacketLayout};
 use crate::service::connect::plugin::ClientEventPlugin;
-use crate::util::EventHandler;
+use crate::state::ExtensionList;
+use crate::util::{EventHandler, RateLimit, TokenBucket};
 use futures::{future, Future, IntoFuture, Sink, Stream};
+use futures::future::Either;
+use log::warn;
+use muonline_packet::Packet;
+use parking_lot::RwLock;
 use std::net::SocketAddr;
-use std::{sync::Arc, time::Duration};
-use tap::{TapOps, TapResultOps};
+use std::{io, sync::Arc, time::Duration};
+use tap::TapOps;
 use tokio::codec::Decoder;
 use tokio::net::TcpStream;
 use tokio::prelude::{FutureExt, StreamExt};
 
+/// Options applied to each new client session.
+#[derive(Debug, Clone)]
+pub struct SessionOptions {
+  pub max_idle_time: Duration,
+  pub max_requests: usize,
+  pub request_rate: Option<RateLimit>,
+  pub realm_list_rate: Option<RateLimit>,
+  pub realm_connect_rate: Option<RateLimit>,
+  pub max_unresponsive_time: Duration,
+  pub ignore_unknown_packets: bool,
+  pub require_handshake: bool,
+  pub welcome_packet: Option<WelcomePacket>,
+  pub extension_timeout: Duration,
+  pub extension_fallback: ExtensionFallback,
+}
+
+impl Default for SessionOptions {
+  fn default() -> Self {
+    SessionOptions {
+      max_idle_time: Duration::from_secs(100),
+      max_requests: 0,
+      request_rate: Some(RateLimit {
+        count: 10,
+        period: Duration::from_secs(1),
+      }),
+      realm_list_rate: Some(RateLimit {
+        count: 5,
+        period: Duration::from_secs(10),
+      }),
+      realm_connect_rate: None,
+      max_unresponsive_time: Duration::from_secs(60),
+      ignore_unknown_packets: false,
+      require_handshake: false,
+      welcome_packet: None,
+      extension_timeout: Duration::from_secs(2),
+      extension_fallback: ExtensionFallback::Ignore,
+    }
+  }
+}
+
 pub struct ClientStreamHandler<R: PacketResponder, P: PacketCodecProvider> {
   on_connect: EventHandler<SocketAddr>,
   on_disconnect: EventHandler<SocketAddr>,
-  on_error: EventHandler<ConnectServiceError>,
-  codec_provider: P,
-  max_idle_time: Duration,
-  max_requests: usize,
-  max_unresponsive_time: Duration,
+  on_error: EventHandler<ClientSessionError>,
+  codec_provider: Arc<P>,
+  layout: Arc<dyn PacketLayout>,
+  extensions: ExtensionList,
+  options: Arc<RwLock<SessionOptions>>,
   responder: Arc<R>,
+  middleware: Arc<PacketResponders>,
 }
 
 impl<R, P> ClientStreamHandler<R, P>
@@ -28,31 +75,32 @@ where
   R: PacketResponder,
   P: PacketCodecProvider,
 {
-  pub fn new(responder: R, codec_provider: P) -> Self {
+  /// Constructs a new handler, reading the shared options for each new session.
+  ///
+  /// The packet layout classifies requests for their rate limits, and any packets handled by an
+  /// extension service are forwarded to it instead of the responder. Both answered and forwarded
+  /// packets pass through the middleware.
+  pub fn new(
+    responder: R,
+    middleware: PacketResponders,
+    codec_provider: P,
+    layout: Arc<dyn PacketLayout>,
+    extensions: ExtensionList,
+    options: Arc<RwLock<SessionOptions>>,
+  ) -> Self {
     ClientStreamHandler {
       on_connect: EventHandler::new(),
       on_disconnect: EventHandler::new(),
       on_error: EventHandler::new(),
-      codec_provider,
-      max_idle_time: Duration::from_secs(100),
-      max_requests: 20,
-      max_unresponsive_time: Duration::from_secs(60),
+      codec_provider: Arc::new(codec_provider),
+      layout,
+      extensions,
+      options,
       responder: Arc::new(responder),
+      middleware: Arc::new(middleware),
     }
   }
 
-  pub fn set_max_idle_time(&mut self, value: Duration) {
-    self.max_idle_time = value;
-  }
-
-  pub fn set_max_requests(&mut self, value: usize) {
-    self.max_requests = value;
-  }
-
-  pub fn set_max_unresponsive_time(&mut self, value: Duration) {
-    self.max_unresponsive_time = value;
-  }
-
   pub fn register_plugin(&self, plugin: impl ClientEventPlugin) {
     let plugin = Arc::new(plugin);
     self
@@ -81,34 +129,83 @@ where
         .map_err(ConnectServiceError::from),
     );
 
-    let (writer, reader) = self.codec_provider.create()
-      // Use a non C3/C4 encrypted TCP codec
-      .framed(stream)
-      // Split the stream value into two separate handles
-      .split();
-
+    let codec_provider = self.codec_provider.clone();
     let responder = self.responder.clone();
-    let max_unresponsive_time = self.max_unresponsive_time;
-
-    let communicate = reader
-      // Prevent idle clients from reserving resources
-      .timeout(self.max_idle_time)
-      // Determine whether it's a timeout or stream error
-      .map_err(ConnectServiceError::from_client_timeout)
-      // Limit the number of client requests allowed
-      .and_then(packet_limiter(self.max_requests))
-      // Map each packet to a corresponding response
-      .and_then(move |packet| responder.respond(&packet))
-      // Ignore any empty responses
-      .filter_map(|packet| packet)
-      // Forward the packets to the client
-      .fold(writer, move |sink, packet| {
-        sink
-          .send(packet)
-          .timeout(max_unresponsive_time)
-          .map_err(ConnectServiceError::from_client_timeout)
-      })
-      .map(|_| ());
+    let middleware = self.middleware.clone();
+    let layout = self.layout.clone();
+    let extensions = self.extensions.clone();
+    let options = self.options.read().clone();
+    let max_unresponsive_time = options.max_unresponsive_time;
+    let extension_timeout = options.extension_timeout;
+    let extension_fallback = options.extension_fallback.clone();
+    let mut state = ProtocolState::new(options.require_handshake);
+
+    // Defer the codec construction until the client has been accepted
+    let communicate = move |socket| future::lazy(move || {
+      let (writer, reader) = codec_provider.create()
+        // Use a non C3/C4 encrypted TCP codec
+        .framed(stream)
+        // Split the stream value into two separate handles
+        .split();
+
+      let requests = reader
+        // Prevent idle clients from reserving resources
+        .timeout(options.max_idle_time)
+        // Determine whether it's a timeout or stream error
+        .map_err(ConnectServiceError::from_client_timeout)
+        // Limit the number and rate of client requests allowed
+        .and_then(request_limiter(layout, &options))
+        // Map each packet to a corresponding response, possibly by an extension service
+        .and_then(move |packet| {
+          let request = match middleware.before(&socket, &state, packet) {
+            Ok(Some(request)) => request,
+            result => return Either::A(future::result(result.map(|_| None))),
+          };
+
+          let response = match extensions.route((request.kind() as u8, request.code())) {
+            Some(extension) => Either::A(match state.check_request() {
+              Ok(()) => Either::A(forward::forward(
+                &extension,
+                socket,
+                request.clone(),
+                extension_timeout,
+                extension_fallback.clone(),
+              )),
+              Err(error) => Either::B(future::err(error)),
+            }),
+            None => Either::B(future::result(responder.respond(&socket, &mut state, &request))),
+          };
+
+          // The middleware sees the state in which the request was answered
+          let middleware = middleware.clone();
+          let answered = state.clone();
+          Either::B(response.and_then(move |response| match response {
+            Some(response) => middleware.after(&socket, &answered, &request, response),
+            None => Ok(None),
+          }))
+        })
+        // Optionally ignore any unrecognized packets
+        .or_else(unknown_packet_filter(options.ignore_unknown_packets))
+        // Ignore any empty responses
+        .filter_map(|packet| packet);
+
+      // Greet the client before any requests are answered
+      let greeting = match options.welcome_packet {
+        Some(ref welcome) => match welcome.to_packet() {
+          Ok(packet) => Either::A(send(writer, packet, max_unresponsive_time)),
+          Err(error) => Either::B(future::err(ServerError::InvalidPacket(error).into())),
+        },
+        None => Either::B(future::ok(writer)),
+      };
+
+      greeting
+        .and_then(move |writer| {
+          // Forward the packets to the client
+          requests.fold(writer, move |sink, packet| {
+            send(sink, packet, max_unresponsive_time)
+          })
+        }).map(|_| ())
+    });
 
     let on_connect = self.on_connect.clone();
     let on_disconnect = self.on_disconnect.clone();
@@ -119,11 +216,14 @@ where
         .dispatch(socket)
         .ok_or(ServerError::ClientRejected.into())
         .into_future()
-        .and_then(|_| communicate)
+        .and_then(move |_| communicate(socket))
         .then(move |result| {
           result
-            .tap_err(|error| on_error.dispatch_ref(error))
-            .tap(|_| on_disconnect.dispatch(socket))
+            .map_err(|error| {
+              let session_error = ClientSessionError { socket, error };
+              on_error.dispatch_ref(&session_error);
+              session_error.error
+            }).tap(|_| on_disconnect.dispatch(socket))
         })
     });
 
@@ -131,15 +231,72 @@ where
   }
 }
 
-/// Returns a packet limiter.
-fn packet_limiter<P>(limit: usize) -> impl FnMut(P) -> Result<P> {
+/// Sends a packet to the client, failing if it is unresponsive.
+fn send<S>(
+  sink: S,
+  packet: Packet,
+  max_unresponsive_time: Duration,
+) -> impl Future<Item = S, Error = ConnectServiceError>
+where
+  S: Sink<SinkItem = Packet, SinkError = io::Error>,
+{
+  sink
+    .send(packet)
+    .timeout(max_unresponsive_time)
+    .map_err(ConnectServiceError::from_client_timeout)
+}
+
+/// Returns a request limiter, enforcing the total count and the rate per request type.
+fn request_limiter(
+  layout: Arc<dyn PacketLayout>,
+  options: &SessionOptions,
+) -> impl FnMut(Packet) -> Result<Packet> {
+  let limit = options.max_requests;
+  let bucket = |rate: Option<RateLimit>| rate.map(|rate| rate.bucket());
+  let mut requests = bucket(options.request_rate);
+  let mut realm_list = bucket(options.realm_list_rate);
+  let mut realm_connect = bucket(options.realm_connect_rate);
   let mut counter = 0;
+
   move |packet| {
     counter += 1;
-    if counter > limit {
-      Err(ClientError::MaxPacketsExceeded.into())
-    } else {
-      Ok(packet)
+    if limit > 0 && counter > limit {
+      Err(ClientError::MaxPacketsExceeded)?;
+    }
+
+    // Any invalid packets are left for the responder to reject
+    let request_type = match layout.decode(&packet) {
+      Ok(ClientRequest::RealmList) => realm_list.as_mut(),
+      Ok(ClientRequest::RealmConnect(_)) => realm_connect.as_mut(),
+      _ => None,
+    };
+
+    // Only take tokens once every bucket has one, so a refused request costs nothing
+    let buckets = requests
+      .as_mut()
+      .into_iter()
+      .chain(request_type)
+      .collect::<Vec<_>>();
+    if !buckets.iter().all(|bucket| bucket.has_token()) {
+      Err(ClientError::RequestRateExceeded)?;
+    }
+
+    for bucket in buckets {
+      bucket.try_take();
+    }
+    Ok(packet)
+  }
+}
+
+/// Returns a filter, ignoring unknown packets if enabled.
+fn unknown_packet_filter(
+  ignore: bool,
+) -> impl FnMut(ConnectServiceError) -> Result<Option<Packet>> {
+  move |error| match error {
+    error @ ConnectServiceError::Client(ClientError::UnknownPacket { .. }) if ignore => {
+      warn!("{}", error);
+      Ok(None)
     }
+    error => Err(error),
   }
 }
@@ -2,7 +2,7 @@ use super::StreamHandler;
 use crate::service::connect::error::{ConnectServiceError, Result, ServerError};
 use crate::service::connect::plugin::ListenerEventPlugin;
 use crate::util::{CloseSignal, EventHandler};
-use futures::{Future, Stream};
+use futures::{future, Future, Stream};
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::{self, net::TcpListener};
@@ -10,16 +10,16 @@ use tokio::{self, net::TcpListener};
 pub struct ClientListener {
   on_startup: EventHandler<SocketAddr>,
   on_error: EventHandler<ConnectServiceError>,
-  socket: SocketAddr,
+  endpoints: Vec<(SocketAddr, Arc<dyn StreamHandler>)>,
   close_signal: CloseSignal,
 }
 
 impl ClientListener {
-  pub fn new(socket: SocketAddr, close_signal: CloseSignal) -> Self {
+  pub fn new(close_signal: CloseSignal) -> Self {
     ClientListener {
       on_startup: EventHandler::new(),
       on_error: EventHandler::new(),
-      socket,
+      endpoints: Vec::new(),
       close_signal,
     }
   }
@@ -34,39 +34,64 @@ impl ClientListener {
       .subscribe_fn(closet!([plugin] move |event| plugin.on_error(event)));
   }
 
+  /// Adds sockets to listen on, sharing one stream handler.
+  pub fn bind(&mut self, sockets: &[SocketAddr], stream_handler: impl StreamHandler) {
+    let stream_handler: Arc<dyn StreamHandler> = Arc::new(stream_handler);
+    self.endpoints.extend(
+      sockets
+        .iter()
+        .map(|&socket| (socket, stream_handler.clone())),
+    );
+  }
+
   /// Starts listening for incoming connections.
-  pub fn listen(self, stream_handler: impl StreamHandler) -> Result<()> {
+  pub fn listen(self) -> Result<()> {
     let ClientListener {
       on_startup,
       on_error,
-      socket,
+      endpoints,
       close_signal,
     } = self;
 
     let close_signal =
       close_signal.map_err(|_| ConnectServiceError::from(ServerError::CloseSignalAborted));
 
-    // Listen on the supplied TCP socket
-    let listener = TcpListener::bind(&socket).map_err(ServerError::Bind)?;
-    let local_addr = listener
-      .local_addr()
-      .map_err(ServerError::CannotResolveAddress)?;
+    let mut local_addrs = Vec::with_capacity(endpoints.len());
+    let mut servers = Vec::with_capacity(endpoints.len());
+
+    for (socket, stream_handler) in endpoints {
+      // Listen on the supplied TCP socket
+      let listener =
+        TcpListener::bind(&socket).map_err(|error| ServerError::Bind(socket, error))?;
+      local_addrs.push(
+        listener
+          .local_addr()
+          .map_err(ServerError::CannotResolveAddress)?,
+      );
 
-    let server = listener
-      // Wait for incoming connections
-      .incoming()
-      // Apply context for any errors
-      .map_err(|error| ServerError::Connection(error).into())
-      // Process each new client connection
-      .for_each(move |stream| {
-        tokio::spawn(stream_handler.handle(stream).map_err(|_| ()));
-        Ok(())
-      })
+      servers.push(
+        listener
+          // Wait for incoming connections
+          .incoming()
+          // Apply context for any errors
+          .map_err(|error| ServerError::Connection(error).into())
+          // Process each new client connection
+          .for_each(move |stream| {
+            tokio::spawn(stream_handler.handle(stream).map_err(|_| ()));
+            Ok(())
+          }),
+      );
+    }
+
+    let server = future::join_all(servers)
+      .map(|_| ())
       // Listen for any cancellation events from the controller
       .select(close_signal);
 
-    if !on_startup.dispatch(local_addr) {
-      Err(ServerError::Abort)?;
+    for local_addr in local_addrs {
+      if !on_startup.dispatch(local_addr) {
+        Err(ServerError::Abort)?;
+      }
     }
 
     tokio::run(server.then(move |result| {
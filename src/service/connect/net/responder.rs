@@ -1,86 +1,154 @@
-use super::PacketResponder;
+use super::{PacketResponder, ProtocolState, RealmFilters};
 use crate::service::connect::error::{ClientError, Result, ServerError};
+use crate::service::connect::layout::{ClientRequest, PacketLayout};
+use crate::service::connect::version::{VersionAction, VersionPolicy};
 use crate::state::RealmServerList;
-use log::warn;
-use muonline_packet::{Packet, PacketEncodable};
-use muonline_protocol::connect::{self, server, Client};
+use crate::util::IpNetwork;
+use log::{info, warn};
+use muonline_packet::Packet;
+use parking_lot::RwLock;
+use std::net::SocketAddr;
+use std::sync::Arc;
 
 pub struct ClientPacketResponder {
-  ignore_unknown_packets: bool,
   realms: RealmServerList,
+  layout: Arc<dyn PacketLayout>,
+  versions: Arc<RwLock<VersionPolicy>>,
+  filters: RealmFilters,
+  staff: Arc<RwLock<Vec<IpNetwork>>>,
+  vip: Arc<RwLock<Vec<IpNetwork>>>,
 }
 
 impl ClientPacketResponder {
-  pub fn new(realms: RealmServerList) -> Self {
+  /// Constructs a new responder, reading the shared version policy for each handshake.
+  ///
+  /// Realms are only listed if they are visible according to each filter. Clients within the
+  /// staff networks may list and join realms which are otherwise unavailable, whilst those within
+  /// the VIP networks may overcommit realms by the overflow margin.
+  pub fn new(
+    realms: RealmServerList,
+    layout: Arc<dyn PacketLayout>,
+    versions: Arc<RwLock<VersionPolicy>>,
+    filters: RealmFilters,
+    staff: Arc<RwLock<Vec<IpNetwork>>>,
+    vip: Arc<RwLock<Vec<IpNetwork>>>,
+  ) -> Self {
     ClientPacketResponder {
       realms,
-      ignore_unknown_packets: false,
+      layout,
+      versions,
+      filters,
+      staff,
+      vip,
     }
   }
 
-  pub fn set_ignore_unknown_packets(&mut self, value: bool) {
-    self.ignore_unknown_packets = value;
+  /// Returns whether a client is within the staff networks.
+  fn is_staff(&self, client: &SocketAddr) -> bool {
+    contains(&self.staff.read(), client)
+  }
+
+  /// Returns whether a client is within the VIP networks.
+  fn is_vip(&self, client: &SocketAddr) -> bool {
+    contains(&self.vip.read(), client)
   }
 }
 
 impl PacketResponder for ClientPacketResponder {
   /// Constructs a response for a client packet.
-  fn respond(&self, packet: &Packet) -> Result<Option<Packet>> {
-    match Client::from_packet(&packet).map_err(ClientError::InvalidPacket)? {
-      Client::ConnectServerRequest(request) => {
-        if request.version == connect::VERSION {
-          server::ConnectServerResult::success()
-            .to_packet()
-            .map_err(ServerError::InvalidPacket)
-            .map(Some)
-            .map_err(From::from)
-        } else {
-          Err(
-            ClientError::VersionMismatch {
-              has: request.version,
-              expected: connect::VERSION,
-            }.into(),
-          )
+  fn respond(
+    &self,
+    client: &SocketAddr,
+    state: &mut ProtocolState,
+    packet: &Packet,
+  ) -> Result<Option<Packet>> {
+    let request = self
+      .layout
+      .decode(&packet)
+      .map_err(ClientError::InvalidPacket)?;
+    match request {
+      ClientRequest::Handshake(_) => state.check_handshake()?,
+      ClientRequest::RealmList | ClientRequest::RealmConnect(_) => state.check_request()?,
+      ClientRequest::Unknown => (),
+    }
+
+    let response = match request {
+      ClientRequest::Handshake(version) => {
+        let versions = self.versions.read();
+        match (versions.action(&version), versions.patch_server()) {
+          (VersionAction::Accept, _) => (),
+          (VersionAction::Deprecated, _) => {
+            warn!("Client accepted with deprecated version {:?}", version)
+          }
+          (VersionAction::Patch, Some(patch)) => {
+            info!(
+              "Client with outdated version {:?} redirected to patch server {}:{}",
+              version, patch.host, patch.port
+            );
+            return patch
+              .to_packet()
+              .map_err(ServerError::InvalidPacket)
+              .map(Some)
+              .map_err(From::from);
+          }
+          (VersionAction::Reject, _) | (VersionAction::Patch, None) => {
+            return Err(ClientError::VersionMismatch { has: version }.into());
+          }
+        }
+
+        state.negotiate(version);
+        self.layout.handshake_result()
+      }
+      ClientRequest::RealmConnect(id) => {
+        // Realms hidden by a filter may not be joined by requesting them directly either
+        let is_hidden = self
+          .realms
+          .get(id)
+          .ok()
+          .map(|realm| realm.clone())
+          .map_or(false, |realm| !self.filters.is_visible(client, &realm));
+        if is_hidden {
+          Err(ClientError::RealmHidden(id))?;
+        }
+
+        let realm = self
+          .realms
+          .joinable(id, self.is_staff(client), self.is_vip(client), |realm| {
+            self.filters.is_visible(client, realm)
+          }).map_err(|error| ClientError::RealmRefused(id, error))?;
+        if realm.id != id {
+          info!("Client redirected from full realm {} to {}", id, realm);
         }
+        self.layout.realm_connect(&realm)
       }
-      Client::RealmServerConnectRequest(server) => self
-        .realms
-        .get(server.id)
-        .map(|realm| server::RealmServerConnect::new(realm.host.clone(), realm.port))
-        .map_err(ServerError::RealmState)
-        .and_then(|response| {
-          response
-            .to_packet()
-            .map_err(ServerError::InvalidPacket)
-            .map(Some)
-        }).map_err(From::from),
-      Client::RealmServerListRequest => {
-        let mut list = Vec::with_capacity(self.realms.len());
-        self
+      ClientRequest::RealmList => {
+        let list = self
           .realms
-          .for_each(|realm| list.push((realm.id, realm.load_factor().into()).into()));
-        server::RealmServerList(list)
-          .to_packet()
-          .map_err(ServerError::InvalidPacket)
-          .map(Some)
-          .map_err(From::from)
+          .listing(self.is_staff(client))
+          .into_iter()
+          .filter(|realm| self.filters.is_visible(client, realm))
+          .collect::<Vec<_>>();
+        self.layout.realm_list(&list)
       }
-      _ => {
+      ClientRequest::Unknown => {
         // Preserve enough bytes to construct a footprint
         let header = [packet.kind() as u8, packet.code()]
           .iter()
           .chain(packet.data().iter().take(2))
           .cloned()
           .collect::<Vec<_>>();
-        let error = ClientError::UnknownPacket { header };
-
-        if self.ignore_unknown_packets {
-          warn!("{}", error);
-          Ok(None)
-        } else {
-          Err(error.into())
-        }
+        return Err(ClientError::UnknownPacket { header }.into());
       }
-    }
+    };
+
+    response
+      .map_err(ServerError::InvalidPacket)
+      .map(Some)
+      .map_err(From::from)
   }
 }
+
+/// Returns whether a client is within any of the networks.
+fn contains(networks: &[IpNetwork], client: &SocketAddr) -> bool {
+  networks.iter().any(|network| network.contains(client.ip()))
+}
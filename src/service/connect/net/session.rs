@@ -0,0 +1,53 @@
+use crate::service::connect::error::{ClientError, Result};
+use muonline_protocol::connect::Version;
+
+/// The protocol state of a client session.
+#[derive(Debug, Clone)]
+pub enum ProtocolState {
+  /// Awaiting the version handshake, before any other request.
+  Handshake,
+  /// Accepting requests, without a negotiated version.
+  Open,
+  /// Accepting requests, after negotiating a version.
+  Negotiated(Version),
+}
+
+impl ProtocolState {
+  /// Returns the initial state of a session.
+  pub fn new(require_handshake: bool) -> Self {
+    if require_handshake {
+      ProtocolState::Handshake
+    } else {
+      ProtocolState::Open
+    }
+  }
+
+  /// Returns the version negotiated by the client, if any.
+  pub fn version(&self) -> Option<&Version> {
+    match self {
+      ProtocolState::Negotiated(version) => Some(version),
+      _ => None,
+    }
+  }
+
+  /// Checks whether a version handshake is allowed.
+  pub fn check_handshake(&self) -> Result<()> {
+    match self {
+      ProtocolState::Negotiated(_) => Err(ClientError::RepeatedHandshake)?,
+      _ => Ok(()),
+    }
+  }
+
+  /// Checks whether any other request is allowed.
+  pub fn check_request(&self) -> Result<()> {
+    match self {
+      ProtocolState::Handshake => Err(ClientError::HandshakeRequired)?,
+      _ => Ok(()),
+    }
+  }
+
+  /// Completes the handshake with the client's version.
+  pub fn negotiate(&mut self, version: Version) {
+    *self = ProtocolState::Negotiated(version);
+  }
+}
@@ -0,0 +1,81 @@
+use super::{hex_bytes, raw_packet};
+use failure::Error;
+use muonline_packet::Packet;
+use serde::{de, Deserialize, Deserializer};
+use std::{io, str::FromStr};
+
+/// A packet sent to each client as soon as it has been accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WelcomePacket {
+  /// The greeting of the original connect server.
+  Stock,
+  /// A packet of raw bytes (e.g `C1 04 00 01`).
+  Custom(Vec<u8>),
+}
+
+impl WelcomePacket {
+  /// The bytes of the original greeting.
+  const STOCK: &'static [u8] = &[0xC1, 0x04, 0x00, 0x01];
+
+  /// Returns the raw bytes of the packet.
+  pub fn bytes(&self) -> &[u8] {
+    match self {
+      WelcomePacket::Stock => Self::STOCK,
+      WelcomePacket::Custom(bytes) => bytes,
+    }
+  }
+
+  /// Decodes the packet, sent as is through the client codec.
+  pub fn to_packet(&self) -> io::Result<Packet> {
+    raw_packet(self.bytes())
+  }
+}
+
+impl FromStr for WelcomePacket {
+  type Err = Error;
+
+  /// Parses either `stock` or a sequence of hex bytes.
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    if value.trim().eq_ignore_ascii_case("stock") {
+      return Ok(WelcomePacket::Stock);
+    }
+
+    hex_bytes(value).map(WelcomePacket::Custom)
+  }
+}
+
+impl<'de> Deserialize<'de> for WelcomePacket {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    String::deserialize(deserializer)?
+      .parse()
+      .map_err(de::Error::custom)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_stock_packet() {
+    assert_eq!("stock".parse::<WelcomePacket>().unwrap(), WelcomePacket::Stock);
+    assert_eq!(" Stock ".parse::<WelcomePacket>().unwrap(), WelcomePacket::Stock);
+    assert_eq!(WelcomePacket::Stock.bytes(), &[0xC1, 0x04, 0x00, 0x01]);
+  }
+
+  #[test]
+  fn parses_hex_bytes() {
+    let packet: WelcomePacket = "C1 05 00 01 ff".parse().unwrap();
+    assert_eq!(packet, WelcomePacket::Custom(vec![0xC1, 0x05, 0x00, 0x01, 0xFF]));
+
+    let packet: WelcomePacket = "c10400 01".parse().unwrap();
+    assert_eq!(packet.bytes(), &[0xC1, 0x04, 0x00, 0x01]);
+  }
+
+  #[test]
+  fn rejects_malformed_packets() {
+    for value in &["", " ", "stocks", "C1 0", "C1 04 00 0G", "0xC1 04"] {
+      assert!(value.parse::<WelcomePacket>().is_err(), "{}", value);
+    }
+  }
+}
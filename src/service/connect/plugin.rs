@@ -1,11 +1,20 @@
-use super::ConnectServiceError;
+use super::error::{ClientSessionError, ConnectServiceError};
+use auto_impl::auto_impl;
 use chashmap::CHashMap;
-use crate::util::EventArgs;
+use crate::util::{canonical_ip, ip_network, EventArgs, RateLimit, TokenBucket};
 use failure::Fail;
 use log::{error, info, warn};
-use std::net::SocketAddr;
+use parking_lot::{Mutex, RwLock};
+use std::mem;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+pub use self::access::{AccessRules, CheckIpAccess};
+pub use self::ban::{load_bans, BanPolicy, CheckClientBans, StrikeWeights};
+
+mod access;
+mod ban;
+
 /// A trait describing a listener event plugin.
 pub trait ListenerEventPlugin: Send + Sync + 'static {
   fn on_startup(&self, _event: &mut EventArgs<SocketAddr>) {}
@@ -13,10 +22,11 @@ pub trait ListenerEventPlugin: Send + Sync + 'static {
 }
 
 /// A trait describing a client event plugin.
+#[auto_impl(Arc)]
 pub trait ClientEventPlugin: Send + Sync + 'static {
   fn on_connect(&self, _event: &mut EventArgs<SocketAddr>) {}
   fn on_disconnect(&self, _event: &mut EventArgs<SocketAddr>) {}
-  fn on_error(&self, _event: &mut EventArgs<ConnectServiceError>) {}
+  fn on_error(&self, _event: &mut EventArgs<ClientSessionError>) {}
 }
 
 /// Plugin logging any listener events.
@@ -47,10 +57,10 @@ impl ClientEventPlugin for ClientEventLogger {
     info!("Client disconnected: {}", event.data());
   }
 
-  fn on_error(&self, event: &mut EventArgs<ConnectServiceError>) {
-    let error = event.data();
+  fn on_error(&self, event: &mut EventArgs<ClientSessionError>) {
+    let ClientSessionError { socket, error } = event.data();
     if !error.connection_reset_by_peer() && !error.reject_by_server() {
-      warn!("Client session — {}", error);
+      warn!("Client session {} — {}", socket, error);
       for cause in (error as &Fail).iter_causes() {
         warn!("— {}", cause);
       }
@@ -58,74 +68,96 @@ impl ClientEventPlugin for ClientEventLogger {
   }
 }
 
-/// Plugin restricting maximum clients per IP.
+/// Plugin restricting maximum clients per IP, grouped by network prefix.
 pub struct CheckMaximumClientsPerIp {
-  clients: CHashMap<SocketAddr, usize>,
-  capacity_per_ip: usize,
+  clients: CHashMap<IpAddr, usize>,
+  capacity_per_ip: AtomicUsize,
+  ipv4_prefix_len: u8,
+  ipv6_prefix_len: u8,
 }
 
 impl CheckMaximumClientsPerIp {
-  pub fn new(capacity_per_ip: usize) -> Self {
+  pub fn new(capacity_per_ip: usize, ipv4_prefix_len: u8, ipv6_prefix_len: u8) -> Self {
     CheckMaximumClientsPerIp {
       clients: CHashMap::new(),
-      capacity_per_ip,
+      capacity_per_ip: AtomicUsize::new(capacity_per_ip),
+      ipv4_prefix_len,
+      ipv6_prefix_len,
     }
   }
+
+  /// Changes the capacity, without affecting any existing clients.
+  pub fn set_capacity_per_ip(&self, capacity_per_ip: usize) {
+    self.capacity_per_ip.store(capacity_per_ip, Ordering::SeqCst);
+  }
+
+  /// Returns the network a client's connection is counted towards.
+  fn network(&self, socket: &SocketAddr) -> IpAddr {
+    ip_network(socket.ip(), self.ipv4_prefix_len, self.ipv6_prefix_len)
+  }
 }
 
 impl ClientEventPlugin for CheckMaximumClientsPerIp {
   fn on_connect(&self, event: &mut EventArgs<SocketAddr>) {
-    let socket = *event.data();
-    let mut is_capacity_reached_for_ip = false;
+    let network = self.network(event.data());
+    let capacity_per_ip = self.capacity_per_ip.load(Ordering::SeqCst);
+    let mut is_capacity_reached_for_ip = capacity_per_ip == 0;
 
     self.clients.upsert(
-      socket,
+      network,
       || 1,
       |count| {
-        is_capacity_reached_for_ip = *count == self.capacity_per_ip;
+        is_capacity_reached_for_ip = *count >= capacity_per_ip;
         *count += 1;
       },
     );
 
     if is_capacity_reached_for_ip {
       warn!(
-        "Client refused from {}; maximum connections reached for IP",
-        socket
+        "Client refused from {}; maximum connections reached for {}",
+        event.data(),
+        network
       );
       event.prevent_default();
     }
   }
 
   fn on_disconnect(&self, event: &mut EventArgs<SocketAddr>) {
-    *self
-      .clients
-      .get_mut(event.data())
-      .expect("Invalid client state") -= 1;
+    // Remove any networks without clients, to prevent the map from growing indefinitely
+    self.clients.alter(self.network(event.data()), |count| {
+      Some(count.expect("Invalid client state") - 1).filter(|&count| count > 0)
+    });
   }
 }
 
 /// Plugin restricting maximum clients.
 pub struct CheckMaximumClients {
   clients: AtomicUsize,
-  capacity: usize,
+  capacity: AtomicUsize,
 }
 
 impl CheckMaximumClients {
   pub fn new(capacity: usize) -> Self {
     CheckMaximumClients {
       clients: AtomicUsize::new(0),
-      capacity,
+      capacity: AtomicUsize::new(capacity),
     }
   }
+
+  /// Changes the capacity, without affecting any existing clients.
+  pub fn set_capacity(&self, capacity: usize) {
+    self.capacity.store(capacity, Ordering::SeqCst);
+  }
 }
 
 impl ClientEventPlugin for CheckMaximumClients {
   fn on_connect(&self, event: &mut EventArgs<SocketAddr>) {
-    if self.clients.fetch_add(1, Ordering::SeqCst) == self.capacity {
+    let capacity = self.capacity.load(Ordering::SeqCst);
+    if self.clients.fetch_add(1, Ordering::SeqCst) >= capacity {
       warn!(
         "Client refused from {}; client capacity reached ({})",
         event.data(),
-        self.capacity
+        capacity
       );
       event.prevent_default();
     }
@@ -135,3 +167,113 @@ impl ClientEventPlugin for CheckMaximumClients {
     self.clients.fetch_sub(1, Ordering::SeqCst);
   }
 }
+
+/// Plugin restricting the rate of new connections, both globally and per IP.
+pub struct CheckConnectionRate {
+  global: Mutex<Option<RateState>>,
+  clients: CHashMap<IpAddr, RateState>,
+  limit_per_ip: RwLock<Option<RateLimit>>,
+  connects: AtomicUsize,
+}
+
+/// The rate of connections from a source.
+struct RateState {
+  bucket: TokenBucket,
+  is_limited: bool,
+}
+
+impl RateState {
+  fn new(limit: RateLimit) -> Self {
+    RateState {
+      bucket: limit.bucket(),
+      is_limited: false,
+    }
+  }
+
+  /// Checks for a token without taking it, returning whether the source just became limited.
+  fn check(&mut self) -> Result<(), bool> {
+    if self.bucket.has_token() {
+      self.is_limited = false;
+      Ok(())
+    } else {
+      Err(!mem::replace(&mut self.is_limited, true))
+    }
+  }
+
+  /// Takes a token, returning whether the source just became limited.
+  fn take(&mut self) -> Result<(), bool> {
+    self.check()?;
+    self.bucket.try_take();
+    Ok(())
+  }
+}
+
+impl CheckConnectionRate {
+  /// The number of connections between each removal of idle IP entries.
+  const SWEEP_INTERVAL: usize = 1024;
+
+  pub fn new(limit: Option<RateLimit>, limit_per_ip: Option<RateLimit>) -> Self {
+    CheckConnectionRate {
+      global: Mutex::new(limit.map(RateState::new)),
+      clients: CHashMap::new(),
+      limit_per_ip: RwLock::new(limit_per_ip),
+      connects: AtomicUsize::new(0),
+    }
+  }
+
+  /// Changes the limits, resetting the rate of all sources.
+  pub fn set_limits(&self, limit: Option<RateLimit>, limit_per_ip: Option<RateLimit>) {
+    *self.global.lock() = limit.map(RateState::new);
+    *self.limit_per_ip.write() = limit_per_ip;
+    self.clients.clear();
+  }
+
+  /// Checks the rate of an IP, only taking a token if the global rate allows the connection.
+  fn check_ip(&self, ip: IpAddr, global_allowed: bool) -> Result<(), bool> {
+    let limit = match *self.limit_per_ip.read() {
+      Some(limit) => limit,
+      None => return Ok(()),
+    };
+
+    if self.connects.fetch_add(1, Ordering::Relaxed) % Self::SWEEP_INTERVAL == 0 {
+      // Remove any sources which are no longer limited
+      self.clients.retain(|_, state| !state.bucket.is_full());
+    }
+
+    let mut result = Ok(());
+    self.clients.alter(ip, |state| {
+      let mut state = state.unwrap_or_else(|| RateState::new(limit));
+      result = if global_allowed {
+        state.take()
+      } else {
+        state.check()
+      };
+      Some(state)
+    });
+    result
+  }
+}
+
+impl ClientEventPlugin for CheckConnectionRate {
+  fn on_connect(&self, event: &mut EventArgs<SocketAddr>) {
+    let ip = canonical_ip(event.data().ip());
+
+    // Both rates are checked before taking from either, so refused clients spend no tokens
+    let mut global = self.global.lock();
+    let global_allowed = global
+      .as_ref()
+      .map_or(true, |state| state.bucket.has_token());
+
+    if let Err(is_newly_limited) = self.check_ip(ip, global_allowed) {
+      if is_newly_limited {
+        warn!("Clients refused from {}; connection rate exceeded for IP", ip);
+      }
+      event.prevent_default();
+    } else if let Err(is_newly_limited) = global.as_mut().map_or(Ok(()), RateState::take) {
+      if is_newly_limited {
+        warn!("Clients refused; global connection rate exceeded");
+      }
+      event.prevent_default();
+    }
+  }
+}
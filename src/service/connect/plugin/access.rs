@@ -0,0 +1,239 @@
+use super::ClientEventPlugin;
+use crate::util::{canonical_ip, EventArgs};
+use crate::Result;
+use failure::{format_err, ResultExt};
+use ipnet::IpNet;
+use log::{error, info, warn};
+use parking_lot::RwLock;
+use serde::Deserialize;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use std::{fs, str::FromStr, sync::Arc};
+
+/// The interval between checking the access list file for changes.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The layout of an access list file.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct AccessListFile {
+  allow: Vec<String>,
+  deny: Vec<String>,
+}
+
+/// Rules refusing clients by IP address or CIDR range.
+///
+/// A client is refused if it matches any denied range, unless it also matches an allowed range.
+/// Denying `0.0.0.0/0` and `::/0` therefore only accepts allowed clients.
+#[derive(Debug, Default, Clone)]
+pub struct AccessRules {
+  allow: Vec<IpNet>,
+  deny: Vec<IpNet>,
+}
+
+impl AccessRules {
+  /// Loads the rules from a TOML file.
+  pub fn load(path: &Path) -> Result<Self> {
+    let content = fs::read_to_string(path)
+      .with_context(|_| format!("Failed to read access list {}", path.display()))?;
+    let rules = content
+      .parse()
+      .with_context(|_| format!("Invalid access list {}", path.display()))?;
+    Ok(rules)
+  }
+
+  /// Returns whether an address is refused or not.
+  pub fn is_refused(&self, ip: IpAddr) -> bool {
+    let ip = canonical_ip(ip);
+    self.deny.iter().any(|net| net.contains(&ip))
+      && !self.is_allowed(ip)
+  }
+
+  /// Returns whether an address is explicitly allowed or not.
+  pub fn is_allowed(&self, ip: IpAddr) -> bool {
+    let ip = canonical_ip(ip);
+    self.allow.iter().any(|net| net.contains(&ip))
+  }
+}
+
+impl FromStr for AccessRules {
+  type Err = failure::Error;
+
+  /// Parses the TOML content of an access list file.
+  fn from_str(content: &str) -> Result<Self> {
+    let file: AccessListFile = toml::from_str(content)?;
+    Ok(AccessRules {
+      allow: parse_networks(&file.allow)?,
+      deny: parse_networks(&file.deny)?,
+    })
+  }
+}
+
+/// Parses a list of IP addresses and CIDR ranges.
+fn parse_networks(entries: &[String]) -> Result<Vec<IpNet>> {
+  entries
+    .iter()
+    .map(|entry| {
+      entry
+        .parse::<IpNet>()
+        .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format_err!("Invalid IP address or CIDR range '{}'", entry))
+    }).collect()
+}
+
+/// Plugin refusing clients by an access list file, reloaded whenever it changes.
+pub struct CheckIpAccess {
+  state: RwLock<AccessListState>,
+}
+
+struct AccessListState {
+  path: Option<PathBuf>,
+  rules: Arc<AccessRules>,
+  modified: Option<SystemTime>,
+  /// Whether the file failed to load at its last modification time, or whilst missing.
+  failed: bool,
+  checked: Instant,
+}
+
+impl CheckIpAccess {
+  pub fn new(path: Option<PathBuf>) -> Self {
+    let plugin = CheckIpAccess {
+      state: RwLock::new(AccessListState {
+        path: None,
+        rules: Arc::default(),
+        modified: None,
+        failed: false,
+        checked: Instant::now(),
+      }),
+    };
+    plugin.set_path(path);
+    plugin
+  }
+
+  /// Changes the access list file, loading it immediately.
+  ///
+  /// The current rules are kept if the file fails to load.
+  pub fn set_path(&self, path: Option<PathBuf>) {
+    let mut state = self.state.write();
+    if path.is_none() {
+      state.rules = Arc::default();
+    }
+    state.path = path;
+    state.modified = None;
+    state.failed = false;
+    Self::reload(&mut state);
+  }
+
+  /// Returns the current rules, reloading them if the file has changed.
+  fn rules(&self) -> Arc<AccessRules> {
+    {
+      let state = self.state.read();
+      if state.path.is_none() || state.checked.elapsed() < CHECK_INTERVAL {
+        return state.rules.clone();
+      }
+    }
+
+    let mut state = self.state.write();
+    if state.checked.elapsed() >= CHECK_INTERVAL {
+      Self::reload(&mut state);
+    }
+    state.rules.clone()
+  }
+
+  /// Loads the access list if it has been modified, keeping the previous rules on failure.
+  fn reload(state: &mut AccessListState) {
+    state.checked = Instant::now();
+    let path = match state.path {
+      Some(ref path) => path.clone(),
+      None => return,
+    };
+
+    // A missing or invalid file is only reported once, until it changes
+    let modified = fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+    if modified == state.modified && (modified.is_some() || state.failed) {
+      return;
+    }
+
+    state.modified = modified;
+    match AccessRules::load(&path) {
+      Ok(rules) => {
+        info!("Access list loaded from {}", path.display());
+        state.rules = Arc::new(rules);
+        state.failed = false;
+      }
+      Err(error) => {
+        state.failed = true;
+        error!("Access list not reloaded — {}", error);
+        for cause in error.iter_causes() {
+          error!("— {}", cause);
+        }
+      }
+    }
+  }
+}
+
+impl ClientEventPlugin for CheckIpAccess {
+  fn on_connect(&self, event: &mut EventArgs<SocketAddr>) {
+    if self.rules().is_refused(event.data().ip()) {
+      warn!("Client refused from {}; denied by access list", event.data());
+      event.prevent_default();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ip(value: &str) -> IpAddr {
+    value.parse().unwrap()
+  }
+
+  #[test]
+  fn parses_addresses_and_ranges() {
+    let rules: AccessRules = "deny = [\"10.0.0.0/8\", \"192.168.1.1\", \"fe80::/10\"]"
+      .parse()
+      .unwrap();
+    assert!(rules.is_refused(ip("10.1.2.3")));
+    assert!(rules.is_refused(ip("192.168.1.1")));
+    assert!(rules.is_refused(ip("fe80::1")));
+    assert!(!rules.is_refused(ip("192.168.1.2")));
+    assert!(!rules.is_refused(ip("11.0.0.1")));
+  }
+
+  #[test]
+  fn allowed_ranges_override_denied_ones() {
+    let rules: AccessRules = "allow = [\"10.0.0.1\"]\ndeny = [\"0.0.0.0/0\", \"::/0\"]"
+      .parse()
+      .unwrap();
+    assert!(!rules.is_refused(ip("10.0.0.1")));
+    assert!(rules.is_refused(ip("10.0.0.2")));
+    assert!(rules.is_refused(ip("::1")));
+    assert!(rules.is_allowed(ip("10.0.0.1")));
+    assert!(!rules.is_allowed(ip("10.0.0.2")));
+  }
+
+  #[test]
+  fn matches_ipv4_mapped_addresses() {
+    let rules: AccessRules = "deny = [\"10.0.0.0/8\"]".parse().unwrap();
+    assert!(rules.is_refused(ip("::ffff:10.0.0.1")));
+  }
+
+  #[test]
+  fn empty_list_refuses_nothing() {
+    let rules: AccessRules = "".parse().unwrap();
+    assert!(!rules.is_refused(ip("10.0.0.1")));
+    assert!(!rules.is_allowed(ip("10.0.0.1")));
+  }
+
+  #[test]
+  fn rejects_malformed_lists() {
+    assert!("deny = [\"10.0.0.256\"]".parse::<AccessRules>().is_err());
+    assert!("deny = [\"10.0.0.0/33\"]".parse::<AccessRules>().is_err());
+    assert!("deny = [\"localhost\"]".parse::<AccessRules>().is_err());
+    assert!("deny = \"10.0.0.1\"".parse::<AccessRules>().is_err());
+    assert!("block = [\"10.0.0.1\"]".parse::<AccessRules>().is_err());
+    assert!("deny = [".parse::<AccessRules>().is_err());
+  }
+}
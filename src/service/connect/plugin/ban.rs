@@ -0,0 +1,315 @@
+use super::ClientEventPlugin;
+use crate::service::connect::error::{ClientError, ClientSessionError, ConnectServiceError};
+use crate::util::{ip_network, seconds, EventArgs};
+use crate::Result;
+use chashmap::CHashMap;
+use failure::{format_err, ResultExt};
+use log::{error, info, warn};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{fs, mem};
+
+/// The strikes added for each type of client error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct StrikeWeights {
+  pub unknown_packet: u32,
+  pub invalid_packet: u32,
+  pub max_packets_exceeded: u32,
+  pub version_mismatch: u32,
+}
+
+impl Default for StrikeWeights {
+  fn default() -> Self {
+    StrikeWeights {
+      unknown_packet: 1,
+      invalid_packet: 1,
+      max_packets_exceeded: 1,
+      version_mismatch: 1,
+    }
+  }
+}
+
+impl StrikeWeights {
+  /// Returns the strikes added for an error.
+  fn strikes(&self, error: &ConnectServiceError) -> u32 {
+    match error {
+      ConnectServiceError::Client(ClientError::UnknownPacket { .. }) => self.unknown_packet,
+      ConnectServiceError::Client(ClientError::InvalidPacket(_)) => self.invalid_packet,
+      ConnectServiceError::Client(ClientError::MaxPacketsExceeded) => self.max_packets_exceeded,
+      ConnectServiceError::Client(ClientError::VersionMismatch { .. }) => self.version_mismatch,
+      _ => 0,
+    }
+  }
+}
+
+/// The policy for temporarily banning abusive clients.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BanPolicy {
+  /// The strikes resulting in a ban, or zero to disable bans.
+  pub threshold: u32,
+  /// The duration of each ban.
+  pub duration: Duration,
+  /// The time until a single strike has expired.
+  pub strike_decay: Duration,
+  pub weights: StrikeWeights,
+  /// The file persisting any active bans across restarts.
+  pub list: Option<PathBuf>,
+  /// Strikes and bans apply to networks of these prefix lengths.
+  pub ipv4_prefix_len: u8,
+  pub ipv6_prefix_len: u8,
+}
+
+impl Default for BanPolicy {
+  fn default() -> Self {
+    BanPolicy {
+      threshold: 0,
+      duration: Duration::from_secs(60 * 60),
+      strike_decay: Duration::from_secs(60),
+      weights: StrikeWeights::default(),
+      list: None,
+      ipv4_prefix_len: 32,
+      ipv6_prefix_len: 64,
+    }
+  }
+}
+
+/// The layout of a ban list file.
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct BanListFile {
+  /// Networks mapped to the UNIX time their ban expires.
+  bans: BTreeMap<String, u64>,
+}
+
+/// Loads any bans from a ban list file, ignoring a missing file.
+pub fn load_bans(path: &Path) -> Result<HashMap<IpAddr, SystemTime>> {
+  if !path.exists() {
+    return Ok(HashMap::new());
+  }
+
+  let content = fs::read_to_string(path)
+    .with_context(|_| format!("Failed to read ban list {}", path.display()))?;
+  let file: BanListFile =
+    toml::from_str(&content).with_context(|_| format!("Invalid ban list {}", path.display()))?;
+
+  file
+    .bans
+    .iter()
+    .map(|(network, until)| {
+      network
+        .parse::<IpAddr>()
+        .map(|network| (network, UNIX_EPOCH + Duration::from_secs(*until)))
+        .map_err(|_| format_err!("Invalid IP address '{}'", network))
+    }).collect()
+}
+
+/// Writes the bans to a ban list file, replacing it atomically.
+fn save_bans(path: &Path, bans: &HashMap<IpAddr, SystemTime>) -> Result<()> {
+  let file = BanListFile {
+    bans: bans
+      .iter()
+      .filter_map(|(network, until)| {
+        let until = until.duration_since(UNIX_EPOCH).ok()?;
+        Some((network.to_string(), until.as_secs()))
+      }).collect(),
+  };
+
+  let content = toml::to_string(&file).context("Failed to serialize ban list")?;
+  let temporary = path.with_extension("tmp");
+  fs::write(&temporary, content)
+    .and_then(|_| fs::rename(&temporary, path))
+    .with_context(|_| format!("Failed to write ban list {}", path.display()))?;
+  Ok(())
+}
+
+/// The strikes of a network, decaying over time.
+struct StrikeState {
+  strikes: f64,
+  updated: Instant,
+}
+
+impl StrikeState {
+  fn new() -> Self {
+    StrikeState {
+      strikes: 0.0,
+      updated: Instant::now(),
+    }
+  }
+
+  /// Returns the strikes remaining after any decay.
+  fn current(&self, strike_decay: Duration) -> f64 {
+    let decay = seconds(strike_decay);
+    if decay > 0.0 {
+      (self.strikes - seconds(self.updated.elapsed()) / decay).max(0.0)
+    } else {
+      0.0
+    }
+  }
+
+  /// Adds strikes after applying any decay, returning the total.
+  fn add(&mut self, strikes: u32, strike_decay: Duration) -> f64 {
+    self.strikes = self.current(strike_decay) + f64::from(strikes);
+    self.updated = Instant::now();
+    self.strikes
+  }
+}
+
+/// Plugin adding strikes for client errors, temporarily banning networks with too many.
+pub struct CheckClientBans {
+  policy: RwLock<BanPolicy>,
+  strikes: CHashMap<IpAddr, StrikeState>,
+  bans: RwLock<HashMap<IpAddr, SystemTime>>,
+  /// Serializes writes of the ban list.
+  saving: Mutex<()>,
+  errors: AtomicUsize,
+}
+
+impl CheckClientBans {
+  /// The number of errors between each removal of decayed strikes.
+  const SWEEP_INTERVAL: usize = 256;
+
+  pub fn new(policy: BanPolicy) -> Self {
+    let plugin = CheckClientBans {
+      policy: RwLock::new(BanPolicy::default()),
+      strikes: CHashMap::new(),
+      bans: RwLock::new(HashMap::new()),
+      saving: Mutex::new(()),
+      errors: AtomicUsize::new(0),
+    };
+    plugin.set_policy(policy);
+    plugin
+  }
+
+  /// Changes the policy, loading any bans from a new ban list.
+  ///
+  /// Existing bans and strikes are kept.
+  pub fn set_policy(&self, policy: BanPolicy) {
+    let list = policy.list.clone();
+    let previous = mem::replace(&mut *self.policy.write(), policy);
+
+    if let Some(ref path) = list {
+      if previous.list.as_ref() != Some(path) {
+        self.load(path);
+      }
+    }
+  }
+
+  /// Returns the expiry of a network's ban, if it is banned.
+  fn banned_until(&self, network: IpAddr) -> Option<SystemTime> {
+    self
+      .bans
+      .read()
+      .get(&network)
+      .cloned()
+      .filter(|&until| until > SystemTime::now())
+  }
+
+  /// Adds strikes to a network, returning whether it reached the threshold.
+  fn strike(&self, network: IpAddr, strikes: u32, policy: &BanPolicy) -> bool {
+    if self.errors.fetch_add(1, Ordering::Relaxed) % Self::SWEEP_INTERVAL == 0 {
+      // Remove any networks whose strikes have expired
+      self
+        .strikes
+        .retain(|_, state| state.current(policy.strike_decay) > 0.0);
+    }
+
+    let mut is_threshold_reached = false;
+    self.strikes.alter(network, |state| {
+      let mut state = state.unwrap_or_else(StrikeState::new);
+      is_threshold_reached =
+        state.add(strikes, policy.strike_decay) >= f64::from(policy.threshold);
+      Some(state).filter(|_| !is_threshold_reached)
+    });
+    is_threshold_reached
+  }
+
+  /// Bans a network for the duration of the policy.
+  fn ban(&self, network: IpAddr, policy: &BanPolicy) {
+    let until = SystemTime::now() + policy.duration;
+    warn!(
+      "Clients banned from {} until {}; strike threshold reached",
+      network,
+      humantime::format_rfc3339_seconds(until)
+    );
+
+    {
+      let mut bans = self.bans.write();
+      let now = SystemTime::now();
+      bans.retain(|_, until| *until > now);
+      bans.insert(network, until);
+    }
+
+    if let Some(ref path) = policy.list {
+      // Each write takes the latest bans, so a concurrent ban can't be overwritten by an older one
+      let _saving = self.saving.lock();
+      let bans = self.bans.read().clone();
+      if let Err(error) = save_bans(path, &bans) {
+        log_error("Ban list not saved", &error);
+      }
+    }
+  }
+
+  /// Merges the bans of a ban list, keeping the latest expiry of each network.
+  fn load(&self, path: &Path) {
+    match load_bans(path) {
+      Ok(loaded) => {
+        let mut bans = self.bans.write();
+        for (network, until) in loaded {
+          let entry = bans.entry(network).or_insert(until);
+          *entry = (*entry).max(until);
+        }
+        info!("Ban list loaded from {}", path.display());
+      }
+      Err(error) => log_error("Ban list not loaded", &error),
+    }
+  }
+}
+
+impl ClientEventPlugin for CheckClientBans {
+  fn on_connect(&self, event: &mut EventArgs<SocketAddr>) {
+    let network = {
+      let policy = self.policy.read();
+      network(event.data(), &policy)
+    };
+    if let Some(until) = self.banned_until(network) {
+      warn!(
+        "Client refused from {}; banned until {}",
+        event.data(),
+        humantime::format_rfc3339_seconds(until)
+      );
+      event.prevent_default();
+    }
+  }
+
+  fn on_error(&self, event: &mut EventArgs<ClientSessionError>) {
+    let policy = self.policy.read().clone();
+    let strikes = policy.weights.strikes(&event.data().error);
+    if policy.threshold == 0 || strikes == 0 {
+      return;
+    }
+
+    let network = network(&event.data().socket, &policy);
+    if self.strike(network, strikes, &policy) {
+      self.ban(network, &policy);
+    }
+  }
+}
+
+/// Returns the network a client's strikes and bans apply to.
+fn network(socket: &SocketAddr, policy: &BanPolicy) -> IpAddr {
+  ip_network(socket.ip(), policy.ipv4_prefix_len, policy.ipv6_prefix_len)
+}
+
+/// Logs an error along with its causes.
+fn log_error(message: &str, error: &failure::Error) {
+  error!("{} — {}", message, error);
+  for cause in error.iter_causes() {
+    error!("— {}", cause);
+  }
+}
@@ -0,0 +1,30 @@
+#[cfg(feature = "scripting")]
+pub use self::hooks::{ScriptHooks, ScriptPacketFilter};
+use serde::Deserialize;
+use std::path::PathBuf;
+
+#[cfg(feature = "scripting")]
+mod hooks;
+
+/// Rhai scripts deciding the connection, packet and realm list policies of a listener.
+///
+/// Each script evaluates to a boolean, and requires the `scripting` feature.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct ScriptPaths {
+  /// Whether a client is accepted, given its `ip` and `port`.
+  pub on_connect: Option<PathBuf>,
+  /// Whether a packet is answered, given the client and the decoded `request`.
+  pub on_packet: Option<PathBuf>,
+  /// Whether a realm is listed and joinable, given the client and the `realm`.
+  pub realm_list: Option<PathBuf>,
+  /// Whether a script failing to evaluate allows what it decides, instead of refusing it.
+  pub fail_open: bool,
+}
+
+impl ScriptPaths {
+  /// Returns whether no scripts are specified.
+  pub fn is_empty(&self) -> bool {
+    self.on_connect.is_none() && self.on_packet.is_none() && self.realm_list.is_none()
+  }
+}
@@ -0,0 +1,210 @@
+use super::ScriptPaths;
+use crate::service::connect::error::Result;
+use crate::service::connect::layout::{ClientRequest, PacketLayout};
+use crate::service::connect::net::{PacketMiddleware, ProtocolState, RealmFilter};
+use crate::service::connect::plugin::ClientEventPlugin;
+use crate::service::connect::version::ClientVersion;
+use crate::state::RealmServer;
+use crate::util::EventArgs;
+use failure::{format_err, ResultExt};
+use log::{error, info, warn};
+use muonline_packet::Packet;
+use parking_lot::RwLock;
+use rhai::{Engine, Scope, AST};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::{fs, sync::Arc};
+
+/// The maximum operations of a single evaluation, preventing endless scripts.
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// The compiled scripts of a listener.
+#[derive(Default)]
+struct CompiledScripts {
+  on_connect: Option<AST>,
+  on_packet: Option<AST>,
+  realm_list: Option<AST>,
+  fail_open: bool,
+}
+
+/// Plugin evaluating the Rhai scripts of a listener.
+///
+/// A script failing to evaluate is logged, and refuses what it decides unless the scripts fail
+/// open.
+pub struct ScriptHooks {
+  engine: Engine,
+  scripts: RwLock<CompiledScripts>,
+}
+
+impl ScriptHooks {
+  pub fn new(paths: &ScriptPaths) -> Self {
+    let hooks = ScriptHooks {
+      engine: engine(),
+      scripts: RwLock::new(CompiledScripts::default()),
+    };
+    hooks.set_paths(paths);
+    hooks
+  }
+
+  /// Compiles a new set of scripts, keeping the previous ones on failure.
+  pub fn set_paths(&self, paths: &ScriptPaths) {
+    match compile(&self.engine, paths) {
+      Ok(scripts) => {
+        if !paths.is_empty() {
+          info!("Scripts compiled");
+        }
+        *self.scripts.write() = scripts;
+      }
+      Err(error) => {
+        error!("Scripts not reloaded — {}", error);
+        for cause in error.iter_causes() {
+          error!("— {}", cause);
+        }
+      }
+    }
+  }
+
+  /// Checks whether each script compiles.
+  pub fn check(paths: &ScriptPaths) -> crate::Result<()> {
+    compile(&engine(), paths).map(|_| ())
+  }
+
+  /// Evaluates a script, defaulting to true if it is missing, and to whether the scripts fail open
+  /// if it fails.
+  fn eval<F>(&self, select: F, scope: &mut Scope) -> bool
+  where
+    F: FnOnce(&CompiledScripts) -> Option<&AST>,
+  {
+    let scripts = self.scripts.read();
+    match select(&scripts) {
+      Some(script) => self
+        .engine
+        .eval_ast_with_scope::<bool>(scope, script)
+        .unwrap_or_else(|error| {
+          error!("Script evaluation failed — {}", error);
+          scripts.fail_open
+        }),
+      None => true,
+    }
+  }
+}
+
+impl ClientEventPlugin for ScriptHooks {
+  fn on_connect(&self, event: &mut EventArgs<SocketAddr>) {
+    let mut scope = client_scope(event.data());
+    if !self.eval(|scripts| scripts.on_connect.as_ref(), &mut scope) {
+      warn!("Client refused from {}; rejected by script", event.data());
+      event.prevent_default();
+    }
+  }
+}
+
+impl RealmFilter for ScriptHooks {
+  fn is_visible(&self, client: &SocketAddr, realm: &RealmServer) -> bool {
+    let mut scope = client_scope(client);
+    scope.push("realm", i64::from(realm.id));
+    scope.push("realmGroup", i64::from(realm.group()));
+    scope.push("realmState", realm.state.to_string());
+    scope.push("realmHost", realm.host.clone());
+    scope.push("realmPort", i64::from(realm.port));
+    scope.push("clients", realm.clients as i64);
+    scope.push("capacity", realm.capacity as i64);
+    self.eval(|scripts| scripts.realm_list.as_ref(), &mut scope)
+  }
+}
+
+/// Middleware evaluating the packet script of a listener, vetoing any rejected packets.
+pub struct ScriptPacketFilter {
+  hooks: Arc<ScriptHooks>,
+  layout: Arc<dyn PacketLayout>,
+}
+
+impl ScriptPacketFilter {
+  /// Constructs a new filter, decoding requests with the listener's packet layout.
+  pub fn new(hooks: Arc<ScriptHooks>, layout: Arc<dyn PacketLayout>) -> Self {
+    ScriptPacketFilter { hooks, layout }
+  }
+}
+
+impl PacketMiddleware for ScriptPacketFilter {
+  fn before(
+    &self,
+    client: &SocketAddr,
+    state: &ProtocolState,
+    request: Packet,
+  ) -> Result<Option<Packet>> {
+    // Any invalid packets are left for the responder to reject
+    let decoded = match self.layout.decode(&request) {
+      Ok(decoded) => decoded,
+      Err(_) => return Ok(Some(request)),
+    };
+
+    let mut scope = client_scope(client);
+    let (name, version, realm) = match decoded {
+      ClientRequest::Handshake(version) => {
+        ("handshake", ClientVersion::from(&version).to_string(), -1)
+      }
+      ClientRequest::RealmList => ("realmList", String::new(), -1),
+      ClientRequest::RealmConnect(id) => ("realmConnect", String::new(), i64::from(id)),
+      ClientRequest::Unknown => ("unknown", String::new(), -1),
+    };
+    let negotiated = state
+      .version()
+      .map(|version| ClientVersion::from(version).to_string())
+      .unwrap_or_default();
+
+    scope.push("request", name.to_string());
+    scope.push("version", version);
+    scope.push("realm", realm);
+    scope.push("kind", i64::from(request.kind() as u8));
+    scope.push("code", i64::from(request.code()));
+    scope.push("negotiated", negotiated);
+
+    if self.hooks.eval(|scripts| scripts.on_packet.as_ref(), &mut scope) {
+      Ok(Some(request))
+    } else {
+      info!("Packet from {} ignored; rejected by script", client);
+      Ok(None)
+    }
+  }
+}
+
+/// Returns a scope with the client's address.
+fn client_scope(client: &SocketAddr) -> Scope<'static> {
+  let mut scope = Scope::new();
+  scope.push("ip", client.ip().to_string());
+  scope.push("port", i64::from(client.port()));
+  scope
+}
+
+/// Returns an engine, limited to a number of operations per evaluation.
+fn engine() -> Engine {
+  let mut engine = Engine::new();
+  engine.set_max_operations(MAX_OPERATIONS);
+  engine
+}
+
+/// Compiles each specified script.
+fn compile(engine: &Engine, paths: &ScriptPaths) -> crate::Result<CompiledScripts> {
+  Ok(CompiledScripts {
+    on_connect: load(engine, paths.on_connect.as_ref())?,
+    on_packet: load(engine, paths.on_packet.as_ref())?,
+    realm_list: load(engine, paths.realm_list.as_ref())?,
+    fail_open: paths.fail_open,
+  })
+}
+
+/// Loads and compiles a single script, if specified.
+fn load(engine: &Engine, path: Option<&PathBuf>) -> crate::Result<Option<AST>> {
+  let path = match path {
+    Some(path) => path,
+    None => return Ok(None),
+  };
+
+  let content = fs::read_to_string(path)
+    .with_context(|_| format!("Failed to read script {}", path.display()))?;
+  let script = engine
+    .compile(&content)
+    .map_err(|error| format_err!("Invalid script {}; {}", path.display(), error))?;
+  Ok(Some(script))
+}
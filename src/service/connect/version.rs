@@ -0,0 +1,320 @@
+use super::net::raw_packet;
+use failure::{format_err, Error};
+use muonline_packet::Packet;
+use muonline_protocol::connect::{self, Version};
+use serde::{de, Deserialize, Deserializer};
+use std::{fmt, io, str::FromStr};
+
+/// A client version, comparable with those received from clients (e.g `1.2.0`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ClientVersion(pub [u8; 3]);
+
+impl<'a> From<&'a Version> for ClientVersion {
+  fn from(version: &'a Version) -> Self {
+    ClientVersion([version.0, version.1, version.2])
+  }
+}
+
+impl FromStr for ClientVersion {
+  type Err = Error;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    let parts = value
+      .trim()
+      .split('.')
+      .map(str::parse)
+      .collect::<Result<Vec<u8>, _>>()
+      .map_err(|_| format_err!("Invalid version '{}'", value.trim()))?;
+    match parts.as_slice() {
+      &[major, minor, patch] => Ok(ClientVersion([major, minor, patch])),
+      _ => Err(format_err!("Expected a version such as '1.2.0'")),
+    }
+  }
+}
+
+impl fmt::Display for ClientVersion {
+  fn fmt(&self, output: &mut fmt::Formatter) -> fmt::Result {
+    let [major, minor, patch] = self.0;
+    write!(output, "{}.{}.{}", major, minor, patch)
+  }
+}
+
+impl<'de> Deserialize<'de> for ClientVersion {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    String::deserialize(deserializer)?
+      .parse()
+      .map_err(de::Error::custom)
+  }
+}
+
+/// An inclusive range of client versions (e.g `1.2.0` or `1.2.0-1.2.5`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionRange {
+  pub first: ClientVersion,
+  pub last: ClientVersion,
+}
+
+impl VersionRange {
+  /// Returns whether a version is within the range.
+  pub fn contains(&self, version: ClientVersion) -> bool {
+    self.first <= version && version <= self.last
+  }
+
+  /// Returns whether the range contains no versions.
+  pub fn is_empty(&self) -> bool {
+    self.first > self.last
+  }
+}
+
+impl FromStr for VersionRange {
+  type Err = Error;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    let mut versions = value.splitn(2, '-');
+    let first = versions.next().unwrap_or_default().parse()?;
+    let last = versions.next().map_or(Ok(first), str::parse)?;
+    Ok(VersionRange { first, last })
+  }
+}
+
+impl fmt::Display for VersionRange {
+  fn fmt(&self, output: &mut fmt::Formatter) -> fmt::Result {
+    write!(output, "{}", self.first)?;
+    if self.last != self.first {
+      write!(output, "-{}", self.last)?;
+    }
+    Ok(())
+  }
+}
+
+impl<'de> Deserialize<'de> for VersionRange {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    String::deserialize(deserializer)?
+      .parse()
+      .map_err(de::Error::custom)
+  }
+}
+
+/// The handling of a client version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VersionAction {
+  /// The client is accepted.
+  Accept,
+  /// The client is accepted, but logged as deprecated.
+  Deprecated,
+  /// The client is refused.
+  Reject,
+  /// The client is sent the address of the patch server.
+  Patch,
+}
+
+/// A range of client versions and how they are handled.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VersionRule {
+  pub range: VersionRange,
+  pub action: VersionAction,
+}
+
+/// A patch server, updating outdated clients to its version.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PatchServer {
+  pub host: String,
+  pub port: u16,
+  pub version: ClientVersion,
+}
+
+impl PatchServer {
+  /// Returns the packet informing a client of the patch server.
+  ///
+  /// The packet is laid out as `C1 size 05 01`, followed by the version, the port in little
+  /// endian and the NUL terminated host.
+  pub fn to_packet(&self) -> io::Result<Packet> {
+    let mut bytes = vec![0xC1, 0x00, 0x05, 0x01];
+    bytes.extend_from_slice(&self.version.0);
+    bytes.extend_from_slice(&[self.port as u8, (self.port >> 8) as u8]);
+    bytes.extend_from_slice(self.host.as_bytes());
+    bytes.push(0);
+
+    if bytes.len() > usize::from(u8::max_value()) {
+      Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "patch server host is too long",
+      ))?;
+    }
+
+    bytes[1] = bytes.len() as u8;
+    raw_packet(&bytes)
+  }
+}
+
+/// Rules determining which client versions are accepted.
+///
+/// The first matching rule applies. Without any rules, only the version of the protocol crate is
+/// accepted. Versions matching no rule are refused, unless they are older than the patch server's
+/// version, in which case the client is redirected to it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VersionPolicy {
+  rules: Vec<VersionRule>,
+  patch: Option<PatchServer>,
+}
+
+impl VersionPolicy {
+  pub fn new(rules: Vec<VersionRule>, patch: Option<PatchServer>) -> Self {
+    VersionPolicy { rules, patch }
+  }
+
+  /// Returns the patch server, if any.
+  pub fn patch_server(&self) -> Option<&PatchServer> {
+    self.patch.as_ref()
+  }
+
+  /// Returns how a client version is handled.
+  pub fn action(&self, version: &Version) -> VersionAction {
+    let client_version = ClientVersion::from(version);
+    let rule = self
+      .rules
+      .iter()
+      .find(|rule| rule.range.contains(client_version));
+
+    match (rule, &self.patch) {
+      (Some(rule), None) if rule.action == VersionAction::Patch => VersionAction::Reject,
+      (Some(rule), _) => rule.action,
+      (None, _) if self.rules.is_empty() && *version == connect::VERSION => VersionAction::Accept,
+      (None, Some(patch)) if client_version < patch.version => VersionAction::Patch,
+      (None, _) => VersionAction::Reject,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn version(value: &str) -> ClientVersion {
+    value.parse().unwrap()
+  }
+
+  #[test]
+  fn parses_versions() {
+    assert_eq!(version("1.2.0"), ClientVersion([1, 2, 0]));
+    assert_eq!(version(" 0.255.9 "), ClientVersion([0, 255, 9]));
+    assert_eq!(version("1.2.0").to_string(), "1.2.0");
+  }
+
+  #[test]
+  fn rejects_malformed_versions() {
+    for value in &["", "1", "1.2", "1.2.0.0", "1.2.x", "1.2.256", "1..2", "-1.2.0"] {
+      assert!(value.parse::<ClientVersion>().is_err(), "{}", value);
+    }
+  }
+
+  #[test]
+  fn parses_single_versions_as_ranges() {
+    let range: VersionRange = "1.2.0".parse().unwrap();
+    assert_eq!(range.first, version("1.2.0"));
+    assert_eq!(range.last, version("1.2.0"));
+    assert_eq!(range.to_string(), "1.2.0");
+  }
+
+  #[test]
+  fn parses_inclusive_ranges() {
+    let range: VersionRange = "1.2.0-1.2.5".parse().unwrap();
+    assert!(!range.is_empty());
+    assert!(range.contains(version("1.2.0")));
+    assert!(range.contains(version("1.2.3")));
+    assert!(range.contains(version("1.2.5")));
+    assert!(!range.contains(version("1.1.9")));
+    assert!(!range.contains(version("1.2.6")));
+    assert_eq!(range.to_string(), "1.2.0-1.2.5");
+  }
+
+  #[test]
+  fn detects_empty_ranges() {
+    let range: VersionRange = "1.2.5-1.2.0".parse().unwrap();
+    assert!(range.is_empty());
+    assert!(!range.contains(version("1.2.3")));
+  }
+
+  fn rule(range: &str, action: VersionAction) -> VersionRule {
+    VersionRule {
+      range: range.parse().unwrap(),
+      action,
+    }
+  }
+
+  fn patch(version: &str) -> Option<PatchServer> {
+    Some(PatchServer {
+      host: "patch.example.com".into(),
+      port: 44405,
+      version: version.parse().unwrap(),
+    })
+  }
+
+  fn action(policy: &VersionPolicy, value: &str) -> VersionAction {
+    let ClientVersion([major, minor, revision]) = version(value);
+    policy.action(&Version(major, minor, revision))
+  }
+
+  #[test]
+  fn accepts_only_the_protocol_version_without_rules() {
+    let current = ClientVersion::from(&connect::VERSION).to_string();
+    let policy = VersionPolicy::default();
+    assert_eq!(action(&policy, &current), VersionAction::Accept);
+    assert_eq!(action(&policy, "255.255.255"), VersionAction::Reject);
+  }
+
+  #[test]
+  fn patches_outdated_versions_without_rules() {
+    let current = ClientVersion::from(&connect::VERSION).to_string();
+    let policy = VersionPolicy::new(Vec::new(), patch("255.255.255"));
+    assert_eq!(action(&policy, &current), VersionAction::Accept);
+    assert_eq!(action(&policy, "255.255.254"), VersionAction::Patch);
+    assert_eq!(action(&policy, "255.255.255"), VersionAction::Reject);
+  }
+
+  #[test]
+  fn applies_the_first_matching_rule() {
+    let rules = vec![
+      rule("1.2.0", VersionAction::Reject),
+      rule("1.2.0-1.2.5", VersionAction::Accept),
+      rule("1.1.0-1.1.9", VersionAction::Deprecated),
+      rule("1.0.0-1.0.9", VersionAction::Patch),
+    ];
+    let policy = VersionPolicy::new(rules, patch("1.3.0"));
+    assert_eq!(action(&policy, "1.2.0"), VersionAction::Reject);
+    assert_eq!(action(&policy, "1.2.5"), VersionAction::Accept);
+    assert_eq!(action(&policy, "1.1.3"), VersionAction::Deprecated);
+    assert_eq!(action(&policy, "1.0.0"), VersionAction::Patch);
+  }
+
+  #[test]
+  fn patches_only_unmatched_outdated_versions() {
+    let rules = vec![
+      rule("1.0.0-1.0.9", VersionAction::Reject),
+      rule("1.2.0", VersionAction::Accept),
+    ];
+    let policy = VersionPolicy::new(rules, patch("1.2.0"));
+    assert_eq!(action(&policy, "1.0.5"), VersionAction::Reject);
+    assert_eq!(action(&policy, "1.1.0"), VersionAction::Patch);
+    assert_eq!(action(&policy, "1.2.0"), VersionAction::Accept);
+    assert_eq!(action(&policy, "1.2.1"), VersionAction::Reject);
+  }
+
+  #[test]
+  fn rejects_patch_rules_without_a_patch_server() {
+    let rules = vec![rule("1.0.0-1.0.9", VersionAction::Patch)];
+    let policy = VersionPolicy::new(rules, None);
+    assert_eq!(action(&policy, "1.0.0"), VersionAction::Reject);
+    assert_eq!(action(&policy, "0.9.0"), VersionAction::Reject);
+  }
+
+  #[test]
+  fn rejects_malformed_ranges() {
+    for value in &["", "-", "1.2.0-", "-1.2.0", "1.2.0-1.2", "1.2.0-1.2.5-1.3.0", "1.2.0..1.2.5"] {
+      assert!(value.parse::<VersionRange>().is_err(), "{}", value);
+    }
+  }
+}
@@ -1,5 +1,5 @@
 pub use self::connect::*;
-pub use self::rpc::{RpcService, RpcServiceConfig};
+pub use self::rpc::{RealmExpiry, RpcService, RpcServiceConfig};
 
 mod connect;
 mod rpc;
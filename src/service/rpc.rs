@@ -1,17 +1,32 @@
-pub use self::config::RpcServiceConfig;
+pub use self::config::{RealmExpiry, RpcServiceConfig};
 use crate::util::{CloseSignal, ThreadController};
-use crate::{state::RealmServerList, Result};
+use crate::state::{ExtensionList, RealmServerList};
+use crate::Result;
 use failure::Fail;
 use futures::Future;
 use grpcio::{Environment, ServerBuilder};
-use log::info;
-use std::sync::Arc;
+use log::{error, info};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::{sync::Arc, time::Duration};
 
+/// Shorthand macro for creating an RPC status error.
+macro_rules! rpcerr {
+  ($e:ident, $($arg:tt)*) => {
+    ::grpcio::RpcStatus::new(::grpcio::RpcStatusCode::$e, Some(format!($($arg)*)))
+  };
+}
+
+mod admin;
 mod config;
+mod extension;
 mod plugin;
 mod proto;
 mod realm;
 
+/// The interval between checking realms for expired status updates.
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Fail, Debug)]
 enum RpcServiceError {
   #[fail(display = "Failed to build service")]
@@ -28,10 +43,15 @@ enum RpcServiceError {
 pub struct RpcService(ThreadController);
 
 impl RpcService {
-  /// Spawns a new RPC service instance.
-  pub fn spawn(config: Arc<impl RpcServiceConfig>, realms: RealmServerList) -> Self {
+  /// Spawns a new RPC service instance, accepting realm and extension registrations alongside
+  /// administrative requests, if enabled.
+  pub fn spawn(
+    config: Arc<impl RpcServiceConfig>,
+    realms: RealmServerList,
+    extensions: ExtensionList,
+  ) -> Self {
     grpcio::redirect_log();
-    let ctl = ThreadController::spawn(move |rx| Self::serve(&*config, realms, rx));
+    let ctl = ThreadController::spawn(move |rx| Self::serve(&*config, realms, extensions, rx));
     RpcService(ctl)
   }
 
@@ -48,15 +68,38 @@ impl RpcService {
   fn serve(
     config: &impl RpcServiceConfig,
     realms: RealmServerList,
+    extensions: ExtensionList,
     close_rx: CloseSignal,
   ) -> Result<()> {
-    let realm_service = realm::RealmRpc::new(realms, close_rx.clone());
-    realm_service.register_plugin(plugin::RealmEventLogger);
+    let realm_service = realm::RealmRpc::new(
+      realms.clone(),
+      config.static_realm_policy(),
+      config.realm_expiry(),
+      close_rx.clone(),
+    );
+    realm_service.register_plugin(plugin::RealmEventLogger::new(realms.clone()));
+    let reaper = config
+      .realm_expiry()
+      .map(|_| Self::spawn_reaper(realm_service.clone()));
     let service = proto::create_realm_service(realm_service);
 
+    let extension_service = extension::ExtensionRpc::new(extensions, close_rx.clone());
+    extension_service.register_plugin(plugin::ExtensionEventLogger);
+    let extension_service = proto::create_extension_service(extension_service);
+
     let environment = Arc::new(Environment::new(1));
-    let mut server = ServerBuilder::new(environment)
+    let mut builder = ServerBuilder::new(environment)
       .register_service(service)
+      .register_service(extension_service);
+
+    // The admin service has no authentication, so it must be enabled explicitly
+    if config.admin() {
+      let admin_service = admin::AdminRpc::new(realms.clone());
+      admin_service.register_plugin(plugin::RealmEventLogger::new(realms));
+      builder = builder.register_service(proto::create_admin_service(admin_service));
+    }
+
+    let mut server = builder
       .bind(config.host(), config.port())
       .build()
       .map_err(RpcServiceError::BuildFailure)?;
@@ -73,6 +116,24 @@ impl RpcService {
       .shutdown()
       .wait()
       .map_err(RpcServiceError::ShutdownFailure);
+
+    if let Some((stop_tx, thread)) = reaper {
+      drop(stop_tx);
+      if thread.join().is_err() {
+        error!("Realm expiry thread panicked");
+      }
+    }
     shutdown_result.and(close_result).map_err(From::from)
   }
+
+  /// Spawns a thread expiring realms without recent status updates, until the sender is dropped.
+  fn spawn_reaper(service: realm::RealmRpc) -> (mpsc::Sender<()>, JoinHandle<()>) {
+    let (stop_tx, stop_rx) = mpsc::channel();
+    let thread = thread::spawn(move || {
+      while let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(EXPIRY_INTERVAL) {
+        service.expire_realms();
+      }
+    });
+    (stop_tx, thread)
+  }
 }
@@ -0,0 +1,62 @@
+use super::{plugin::RealmEventPlugin, proto};
+use crate::state::{RealmServer, RealmServerId, RealmServerList, RealmState};
+use crate::util::EventHandler;
+use futures::Future;
+use grpcio::{RpcContext, RpcStatus, UnarySink};
+use std::sync::Arc;
+use try_from::TryFrom;
+
+#[derive(Clone)]
+pub struct AdminRpc {
+  on_update: EventHandler<RealmServer>,
+  on_error: EventHandler<grpcio::Error>,
+  realms: RealmServerList,
+}
+
+impl AdminRpc {
+  pub fn new(realms: RealmServerList) -> Self {
+    AdminRpc {
+      on_update: EventHandler::new(),
+      on_error: EventHandler::new(),
+      realms,
+    }
+  }
+
+  pub fn register_plugin(&self, plugin: impl RealmEventPlugin) {
+    let plugin = Arc::new(plugin);
+    self
+      .on_update
+      .subscribe_fn(closet!([plugin] move |event| plugin.on_update(event)));
+    self
+      .on_error
+      .subscribe_fn(closet!([plugin] move |event| plugin.on_error(event)));
+  }
+
+  fn set_state(&self, params: &proto::RealmStateParams) -> Result<(), RpcStatus> {
+    let id = RealmServerId::try_from(params.get_id())
+      .map_err(|_| rpcerr!(InvalidArgument, "Invalid realm ID {}", params.get_id()))?;
+    let realm = self
+      .realms
+      .set_state(id, RealmState::from(params.get_state()))
+      .map_err(|error| rpcerr!(NotFound, "Realm state change failed: {}", error))?;
+    self.on_update.dispatch_ref(&realm);
+    Ok(())
+  }
+}
+
+impl proto::AdminService for AdminRpc {
+  fn set_realm_state(
+    &self,
+    ctx: RpcContext,
+    params: proto::RealmStateParams,
+    sink: UnarySink<proto::RealmStateResult>,
+  ) {
+    let on_error = self.on_error.clone();
+    let response = match self.set_state(&params) {
+      Ok(()) => sink.success(proto::RealmStateResult::new()),
+      Err(error) => sink.fail(error),
+    }.map_err(move |error| { on_error.dispatch(error); });
+
+    ctx.spawn(response);
+  }
+}
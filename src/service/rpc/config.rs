@@ -1,5 +1,25 @@
+use crate::state::{StaleRealmDisplay, StaticRealmPolicy};
+use std::time::Duration;
+
+/// Deadlines for realms registered over RPC without recent status updates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RealmExpiry {
+  /// The time after the last status update until a realm is stale.
+  pub ttl: Duration,
+  /// The time a realm remains stale until it is evicted.
+  pub eviction_delay: Duration,
+  /// How a realm is listed whilst stale.
+  pub display: StaleRealmDisplay,
+}
+
 pub trait RpcServiceConfig: Send + Sync + 'static {
   fn host(&self) -> &str;
 
   fn port(&self) -> u16;
+
+  fn admin(&self) -> bool;
+
+  fn static_realm_policy(&self) -> StaticRealmPolicy;
+
+  fn realm_expiry(&self) -> Option<RealmExpiry>;
 }
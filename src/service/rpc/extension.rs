@@ -0,0 +1,151 @@
+use super::{plugin::ExtensionEventPlugin, proto};
+use crate::state::{Extension, ExtensionList, ForwardedPacket, PacketCode};
+use crate::util::{CloseSignal, EventHandler, StreamExt};
+use futures::future::Either;
+use futures::sync::mpsc;
+use futures::{Future, Sink, Stream};
+use grpcio::{DuplexSink, RequestStream, RpcContext, RpcStatus, WriteFlags};
+use std::sync::Arc;
+use try_from::TryFrom;
+
+#[derive(Clone)]
+pub struct ExtensionRpc {
+  on_register: EventHandler<Extension>,
+  on_deregister: EventHandler<Extension>,
+  on_error: EventHandler<grpcio::Error>,
+  close_rx: CloseSignal,
+  extensions: ExtensionList,
+}
+
+impl ExtensionRpc {
+  pub fn new(extensions: ExtensionList, close_rx: CloseSignal) -> Self {
+    ExtensionRpc {
+      on_register: EventHandler::new(),
+      on_deregister: EventHandler::new(),
+      on_error: EventHandler::new(),
+      extensions,
+      close_rx,
+    }
+  }
+
+  pub fn register_plugin(&self, plugin: impl ExtensionEventPlugin) {
+    let plugin = Arc::new(plugin);
+    self
+      .on_register
+      .subscribe_fn(closet!([plugin] move |event| plugin.on_register(event)));
+    self
+      .on_deregister
+      .subscribe_fn(closet!([plugin] move |event| plugin.on_deregister(event)));
+    self
+      .on_error
+      .subscribe_fn(closet!([plugin] move |event| plugin.on_error(event)));
+  }
+
+  fn add_extension(
+    &self,
+    definition: proto::ExtensionParams_ExtensionDefinition,
+  ) -> Result<(Arc<Extension>, mpsc::UnboundedReceiver<ForwardedPacket>), RpcStatus> {
+    let codes = definition
+      .get_codes()
+      .iter()
+      .cloned()
+      .map(PacketCode::try_from)
+      .collect::<Result<Vec<_>, _>>()
+      .map_err(|error| rpcerr!(InvalidArgument, "Extension parsing failed: {}", error))?;
+    let (extension, requests) = self
+      .extensions
+      .add(definition.get_name().into(), codes)
+      .map_err(|error| rpcerr!(InvalidArgument, "Extension registration failed: {}", error))?;
+    self.on_register.dispatch_ref(&*extension);
+    Ok((extension, requests))
+  }
+
+  fn remove_extension(&self, extension: &Arc<Extension>) {
+    self.extensions.remove(extension);
+    self.on_deregister.dispatch_ref(&**extension);
+  }
+}
+
+impl proto::ExtensionService for ExtensionRpc {
+  fn register_extension(
+    &self,
+    ctx: RpcContext,
+    stream: RequestStream<proto::ExtensionParams>,
+    sink: DuplexSink<proto::ClientPacket>,
+  ) {
+    let stream = stream
+      // Apply context for any potential errors
+      .map_err(|error| rpcerr!(Aborted, "Stream closed: {}", error))
+      // Require the kind field to be specified
+      .and_then(|input| input.kind.ok_or_else(|| rpcerr!(InvalidArgument, "Kind not specified")));
+
+    let this = self.clone();
+    let registration = stream
+      // Require one item for registering
+      .next_or_else(|| rpcerr!(Cancelled, "Missing input"))
+      // Process the extension registration
+      .and_then(closet!([this] move |(input, stream)| {
+        let definition = matches_opt!(input, proto::ExtensionParams_oneof_kind::definition(x) => x)
+          .ok_or_else(|| rpcerr!(InvalidArgument, "Expected extension definition"))?;
+        let (extension, requests) = this.add_extension(definition)?;
+        Ok((extension, requests, stream))
+      }));
+
+    let session = registration.then(move |result| {
+      let (extension, requests, stream) = match result {
+        Ok(registration) => registration,
+        // Notify the extension of a failed registration, whilst the sink is still unused
+        Err(error) => return Either::A(sink.fail(error)),
+      };
+
+      // Forward each matching client packet to the extension
+      let forward_packets = sink
+        .send_all(
+          requests
+            .map(|packet| (proto::ClientPacket::from(packet), WriteFlags::default()))
+            .map_err(|_| grpcio::Error::RemoteStopped),
+        ).map(|_| ())
+        .map_err(|error| rpcerr!(Aborted, "Forwarding failed: {}", error));
+
+      // Complete the forwarded packets with each response
+      let process_responses = stream.for_each(closet!([extension] move |input| {
+        let response = matches_opt!(input, proto::ExtensionParams_oneof_kind::response(x) => x)
+          .ok_or_else(|| rpcerr!(InvalidArgument, "Expected packet response"))?;
+        extension.respond(response.get_id() as usize, response.get_packet().to_vec());
+        Ok(())
+      }));
+
+      Either::B(
+        forward_packets
+          .select(process_responses)
+          .map(|_| ())
+          .map_err(|(error, _)| grpcio::Error::RpcFailure(error))
+          // Remove the extension after deregistering
+          .then(move |result| {
+            this.remove_extension(&extension);
+            result
+          }),
+      )
+    });
+
+    let on_error = self.on_error.clone();
+    let shutdown = self
+      .close_rx
+      .clone()
+      .then(|_| Err(grpcio::Error::RpcFailure(rpcerr!(Unavailable, "Shutting down"))));
+    let session = session
+      // Check for a potential close signal
+      .select(shutdown)
+      // Report the outcome, since the sink is owned by the forwarding once registered
+      .then(move |result| match result {
+        Ok(_) => Ok(()),
+        Err((error, _)) => {
+          on_error.dispatch(error);
+          Err(())
+        }
+      });
+
+    // Dispatch the session
+    ctx.spawn(session);
+  }
+}
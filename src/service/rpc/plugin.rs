@@ -1,32 +1,91 @@
-use crate::state::RealmServer;
+use crate::state::{Extension, RealmServer, RealmServerList};
 use crate::util::EventArgs;
-use log::{error, info};
+use log::{error, info, warn};
+use std::fmt;
+
+/// The reason a realm was deregistered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeregisterReason {
+  /// The registration stream was closed.
+  Closed,
+  /// No status updates were received before the eviction deadline.
+  Expired,
+}
+
+impl fmt::Display for DeregisterReason {
+  fn fmt(&self, output: &mut fmt::Formatter) -> fmt::Result {
+    output.write_str(match self {
+      DeregisterReason::Closed => "closed",
+      DeregisterReason::Expired => "expired",
+    })
+  }
+}
 
 /// A trait describing a realm event plugin.
 pub trait RealmEventPlugin: Send + Sync + 'static {
   fn on_register(&self, _event: &mut EventArgs<RealmServer>) {}
-  fn on_deregister(&self, _event: &mut EventArgs<RealmServer>) {}
+  fn on_deregister(&self, _event: &mut EventArgs<(RealmServer, DeregisterReason)>) {}
   fn on_update(&self, _event: &mut EventArgs<RealmServer>) {}
+  fn on_stale(&self, _event: &mut EventArgs<RealmServer>) {}
   fn on_error(&self, _event: &mut EventArgs<grpcio::Error>) {}
 }
 
-/// Plugin logging any realm events.
-pub struct RealmEventLogger;
+/// Plugin logging any realm events, alongside the group of (de)registered realms.
+pub struct RealmEventLogger {
+  realms: RealmServerList,
+}
+
+impl RealmEventLogger {
+  pub fn new(realms: RealmServerList) -> Self {
+    RealmEventLogger { realms }
+  }
+}
 
 impl RealmEventPlugin for RealmEventLogger {
   fn on_register(&self, event: &mut EventArgs<RealmServer>) {
-    info!("Realm registered: {}", event.data());
+    let realm = event.data();
+    info!("Realm registered: {} in {}", realm, self.realms.group(realm.group()));
   }
 
-  fn on_deregister(&self, event: &mut EventArgs<RealmServer>) {
-    info!("Realm deregistered: {}", event.data());
+  fn on_deregister(&self, event: &mut EventArgs<(RealmServer, DeregisterReason)>) {
+    let (ref realm, reason) = *event.data();
+    let group = self.realms.group(realm.group());
+    info!("Realm deregistered ({}): {} in {}", reason, realm, group);
   }
 
   fn on_update(&self, event: &mut EventArgs<RealmServer>) {
     info!("Realm updated: {}", event.data());
   }
 
+  fn on_stale(&self, event: &mut EventArgs<RealmServer>) {
+    warn!("Realm stale without status updates: {}", event.data());
+  }
+
   fn on_error(&self, event: &mut EventArgs<grpcio::Error>) {
     error!("Realm RPC — {}", event.data());
   }
 }
+
+/// A trait describing an extension event plugin.
+pub trait ExtensionEventPlugin: Send + Sync + 'static {
+  fn on_register(&self, _event: &mut EventArgs<Extension>) {}
+  fn on_deregister(&self, _event: &mut EventArgs<Extension>) {}
+  fn on_error(&self, _event: &mut EventArgs<grpcio::Error>) {}
+}
+
+/// Plugin logging any extension events.
+pub struct ExtensionEventLogger;
+
+impl ExtensionEventPlugin for ExtensionEventLogger {
+  fn on_register(&self, event: &mut EventArgs<Extension>) {
+    info!("Extension registered: {}", event.data());
+  }
+
+  fn on_deregister(&self, event: &mut EventArgs<Extension>) {
+    info!("Extension deregistered: {}", event.data());
+  }
+
+  fn on_error(&self, event: &mut EventArgs<grpcio::Error>) {
+    error!("Extension RPC — {}", event.data());
+  }
+}
@@ -2,11 +2,19 @@ use crate::{state, Result};
 use failure::{format_err, Error, ResultExt};
 use try_from::TryFrom;
 
+pub use self::admin::*;
+pub use self::admin_grpc::*;
 pub use self::connectserver::*;
 pub use self::connectserver_grpc::*;
+pub use self::extension::*;
+pub use self::extension_grpc::*;
 
+mod admin;
+mod admin_grpc;
 mod connectserver;
 mod connectserver_grpc;
+mod extension;
+mod extension_grpc;
 
 impl TryFrom<RealmParams_RealmDefinition> for state::RealmServer {
   type Err = Error;
@@ -19,6 +27,8 @@ impl TryFrom<RealmParams_RealmDefinition> for state::RealmServer {
       port: u16::try_from(definition.get_port()).context("Invalid port specified")?,
       clients: status.get_clients() as usize,
       capacity: status.get_capacity() as usize,
+      origin: state::RealmOrigin::Rpc,
+      state: state::RealmState::Online,
     };
 
     if server.clients > server.capacity {
@@ -28,3 +38,34 @@ impl TryFrom<RealmParams_RealmDefinition> for state::RealmServer {
     Ok(server)
   }
 }
+
+impl From<RealmStateParams_State> for state::RealmState {
+  fn from(state: RealmStateParams_State) -> Self {
+    match state {
+      RealmStateParams_State::ONLINE => state::RealmState::Online,
+      RealmStateParams_State::MAINTENANCE => state::RealmState::Maintenance,
+      RealmStateParams_State::HIDDEN => state::RealmState::Hidden,
+      RealmStateParams_State::STAFF_ONLY => state::RealmState::StaffOnly,
+    }
+  }
+}
+
+impl TryFrom<PacketCode> for state::PacketCode {
+  type Err = Error;
+
+  fn try_from(code: PacketCode) -> Result<Self> {
+    let kind = u8::try_from(code.get_kind()).context("Invalid packet kind specified")?;
+    let code = u8::try_from(code.get_code()).context("Invalid packet code specified")?;
+    Ok((kind, code))
+  }
+}
+
+impl From<state::ForwardedPacket> for ClientPacket {
+  fn from(forwarded: state::ForwardedPacket) -> Self {
+    let mut packet = ClientPacket::new();
+    packet.set_id(forwarded.id as u64);
+    packet.set_client(forwarded.client.to_string());
+    packet.set_packet(forwarded.bytes);
+    packet
+  }
+}
@@ -1,37 +1,63 @@
-use super::{plugin::RealmEventPlugin, proto};
-use crate::state::{RealmServer, RealmServerId, RealmServerList};
+use super::plugin::{DeregisterReason, RealmEventPlugin};
+use super::{proto, RealmExpiry};
+use crate::state::{RealmServer, RealmServerId, RealmServerList, RealmState};
+use crate::state::{StaleRealmDisplay, StaticRealmPolicy};
 use crate::util::{CloseSignal, EventHandler, StreamExt};
-use futures::{Future, Stream};
-use grpcio::{ClientStreamingSink, RequestStream, RpcContext, RpcStatus, RpcStatusCode};
+use futures::future::{self, Either};
+use futures::{sync::oneshot, Future, Stream};
+use grpcio::{ClientStreamingSink, RequestStream, RpcContext, RpcStatus};
+use parking_lot::Mutex;
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::time::Instant;
+use std::{mem, sync::Arc};
 use try_from::TryFrom;
 
-/// Shorthand macro for creating an RPC status error.
-macro_rules! rpcerr {
-  ($e:ident, $($arg:tt)*) => {
-    RpcStatus::new(RpcStatusCode::$e, Some(format!($($arg)*)))
-  };
+/// Sentinel for a session without a registered realm.
+const UNREGISTERED: usize = usize::max_value();
+
+/// The status updates of a registered realm.
+struct Heartbeat {
+  /// The realm ID of the registering session, shared with it.
+  realm_id: Arc<AtomicUsize>,
+  updated: Instant,
+  stale: bool,
+  /// The state of a realm before it was hidden for being stale.
+  hidden_from: Option<RealmState>,
+  evict_tx: Option<oneshot::Sender<()>>,
 }
 
 #[derive(Clone)]
 pub struct RealmRpc {
   on_register: EventHandler<RealmServer>,
-  on_deregister: EventHandler<RealmServer>,
+  on_deregister: EventHandler<(RealmServer, DeregisterReason)>,
   on_update: EventHandler<RealmServer>,
+  on_stale: EventHandler<RealmServer>,
   on_error: EventHandler<grpcio::Error>,
   close_rx: CloseSignal,
   realms: RealmServerList,
+  static_policy: StaticRealmPolicy,
+  expiry: Option<RealmExpiry>,
+  heartbeats: Arc<Mutex<HashMap<RealmServerId, Heartbeat>>>,
 }
 
 impl RealmRpc {
-  pub fn new(realms: RealmServerList, close_rx: CloseSignal) -> Self {
+  pub fn new(
+    realms: RealmServerList,
+    static_policy: StaticRealmPolicy,
+    expiry: Option<RealmExpiry>,
+    close_rx: CloseSignal,
+  ) -> Self {
     RealmRpc {
       on_register: EventHandler::new(),
       on_deregister: EventHandler::new(),
       on_update: EventHandler::new(),
+      on_stale: EventHandler::new(),
       on_error: EventHandler::new(),
       realms,
+      static_policy,
+      expiry,
+      heartbeats: Arc::new(Mutex::new(HashMap::new())),
       close_rx,
     }
   }
@@ -47,11 +73,90 @@ impl RealmRpc {
     self
       .on_deregister
       .subscribe_fn(closet!([plugin] move |event| plugin.on_deregister(event)));
+    self
+      .on_stale
+      .subscribe_fn(closet!([plugin] move |event| plugin.on_stale(event)));
     self
       .on_error
       .subscribe_fn(closet!([plugin] move |event| plugin.on_error(event)));
   }
 
+  /// Marks realms without recent status updates as stale, and evicts them after a further delay.
+  pub fn expire_realms(&self) {
+    let expiry = match self.expiry {
+      Some(expiry) => expiry,
+      None => return,
+    };
+
+    let mut stale = Vec::new();
+    let mut expired = Vec::new();
+    self.heartbeats.lock().retain(|&id, heartbeat| {
+      let elapsed = heartbeat.updated.elapsed();
+      if elapsed >= expiry.ttl + expiry.eviction_delay {
+        // The session may have deregistered the realm concurrently
+        if heartbeat.realm_id.swap(UNREGISTERED, Ordering::Relaxed) != UNREGISTERED {
+          expired.extend(self.realms.remove(id).ok());
+          if let Some(evict_tx) = heartbeat.evict_tx.take() {
+            let _ = evict_tx.send(());
+          }
+        }
+        return false;
+      }
+
+      if elapsed >= expiry.ttl && !heartbeat.stale {
+        heartbeat.stale = true;
+        if let Ok(mut realm) = self.realms.get_mut(id) {
+          match expiry.display {
+            StaleRealmDisplay::Full => realm.clients = realm.capacity,
+            StaleRealmDisplay::Hidden => {
+              heartbeat.hidden_from = Some(mem::replace(&mut realm.state, RealmState::Hidden))
+            }
+          }
+          stale.push(realm.clone());
+        }
+      }
+      true
+    });
+
+    for realm in &stale {
+      self.on_stale.dispatch_ref(realm);
+    }
+
+    for realm in expired {
+      self.on_deregister.dispatch((realm, DeregisterReason::Expired));
+    }
+  }
+
+  /// Starts tracking the status updates of a realm, if they expire.
+  fn track_realm(
+    &self,
+    id: RealmServerId,
+    realm_id: Arc<AtomicUsize>,
+    evict_tx: oneshot::Sender<()>,
+  ) {
+    if self.expiry.is_some() {
+      self.heartbeats.lock().insert(
+        id,
+        Heartbeat {
+          realm_id,
+          updated: Instant::now(),
+          stale: false,
+          hidden_from: None,
+          evict_tx: Some(evict_tx),
+        },
+      );
+    }
+  }
+
+  /// Refreshes the heartbeat of a realm, returning any state to restore after being stale.
+  fn refresh_realm(&self, id: RealmServerId) -> Option<RealmState> {
+    let mut heartbeats = self.heartbeats.lock();
+    let heartbeat = heartbeats.get_mut(&id)?;
+    heartbeat.updated = Instant::now();
+    heartbeat.stale = false;
+    heartbeat.hidden_from.take()
+  }
+
   fn add_realm(
     &self,
     realm: proto::RealmParams_RealmDefinition,
@@ -59,10 +164,10 @@ impl RealmRpc {
     let realm = RealmServer::try_from(realm)
       .map_err(|error| rpcerr!(InvalidArgument, "Realm parsing failed: {}", error))?;
     let realm_id = realm.id;
-    self
-      .realms
-      .add(realm)
-      .map_err(|error| rpcerr!(InvalidArgument, "Realm registration failed: {}", error))?;
+    match self.static_policy {
+      StaticRealmPolicy::Reject => self.realms.add(realm),
+      StaticRealmPolicy::Override => self.realms.add_override(realm),
+    }.map_err(|error| rpcerr!(InvalidArgument, "Realm registration failed: {}", error))?;
     self
       .on_register
       .dispatch_ref(&*self.realms.get(realm_id).expect("Invalid realm state"));
@@ -74,22 +179,42 @@ impl RealmRpc {
     id: RealmServerId,
     status: &proto::RealmParams_RealmStatus,
   ) -> Result<(), RpcStatus> {
+    let restored_state = self.refresh_realm(id);
     self
       .realms
       .get_mut(id)
       .map(|mut realm| {
         realm.clients = status.get_clients() as usize;
         realm.capacity = status.get_capacity() as usize;
+        // Any state changed by an administrator whilst stale is kept
+        if let (Some(state), RealmState::Hidden) = (restored_state, realm.state) {
+          realm.state = state;
+        }
         self.on_update.dispatch_ref(&*realm);
       }).map_err(|error| rpcerr!(InvalidArgument, "Realm update failed: {}", error))
   }
 
-  fn remove_realm(&self, id: RealmServerId) -> Result<(), RpcStatus> {
+  fn remove_realm(
+    &self,
+    id: RealmServerId,
+    realm_id: &Arc<AtomicUsize>,
+  ) -> Result<(), RpcStatus> {
+    {
+      // The realm may have been registered again after being evicted
+      let mut heartbeats = self.heartbeats.lock();
+      if heartbeats
+        .get(&id)
+        .map_or(false, |heartbeat| Arc::ptr_eq(&heartbeat.realm_id, realm_id))
+      {
+        heartbeats.remove(&id);
+      }
+    }
+
     self
       .realms
       .remove(id)
       .map_err(|error| rpcerr!(Internal, "Realm deregister failed: {}", error))
-      .map(|realm| self.on_deregister.dispatch_ref(&realm))?;
+      .map(|realm| self.on_deregister.dispatch((realm, DeregisterReason::Closed)))?;
     Ok(())
   }
 }
@@ -108,7 +233,8 @@ impl proto::RealmService for RealmRpc {
       .and_then(|input| input.kind.ok_or_else(|| rpcerr!(InvalidArgument, "Kind not specified")));
 
     let this = self.clone();
-    let realm_id = Arc::new(AtomicUsize::new(0));
+    let realm_id = Arc::new(AtomicUsize::new(UNREGISTERED));
+    let (evict_tx, evict_rx) = oneshot::channel();
 
     let wait_for_realm_register = stream
       // Require one item for registering
@@ -117,20 +243,37 @@ impl proto::RealmService for RealmRpc {
       .and_then(closet!([this, realm_id] move |(input, stream)| {
         let definition = matches_opt!(input, proto::RealmParams_oneof_kind::definition(x) => x)
           .ok_or_else(|| rpcerr!(InvalidArgument, "Expected realm definition"))?;
-        realm_id.store(this.add_realm(definition)? as usize, Ordering::Relaxed);
+        let id = this.add_realm(definition)?;
+        realm_id.store(id as usize, Ordering::Relaxed);
+        this.track_realm(id, realm_id.clone(), evict_tx);
         Ok(stream)
       })).flatten_stream();
 
+    // Resolves once the realm is evicted, since the sender is dropped otherwise
+    let wait_for_eviction = evict_rx.then(|result| match result {
+      Ok(_) => Either::A(future::err(rpcerr!(DeadlineExceeded, "Realm expired"))),
+      Err(_) => Either::B(future::empty()),
+    });
+
     let process_realm_updates = wait_for_realm_register
       // Update the internal state for each status update
       .for_each(closet!([this, realm_id] move |input| {
         let status = matches_opt!(input, proto::RealmParams_oneof_kind::status(x) => x)
           .ok_or_else(|| rpcerr!(InvalidArgument, "Expected realm status"))?;
-        this.update_realm(realm_id.load(Ordering::Relaxed) as RealmServerId, &status)
+        match realm_id.load(Ordering::Relaxed) {
+          UNREGISTERED => Err(rpcerr!(DeadlineExceeded, "Realm expired")),
+          id => this.update_realm(id as RealmServerId, &status),
+        }
       }))
-      // Remove the realm after deregistering
+      // End the session if the realm expires
+      .select(wait_for_eviction)
+      .map_err(|(error, _)| error)
+      // Remove the realm after deregistering, unless it has been evicted
       .then(closet!([this] move |result| {
-        result.and(this.remove_realm(realm_id.load(Ordering::Relaxed) as RealmServerId))
+        match realm_id.swap(UNREGISTERED, Ordering::Relaxed) {
+          UNREGISTERED => result.map(|_| ()),
+          id => result.map(|_| ()).and(this.remove_realm(id as RealmServerId, &realm_id)),
+        }
       }));
 
     let session = process_realm_updates
@@ -147,3 +290,178 @@ impl proto::RealmService for RealmRpc {
     ctx.spawn(session);
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::state::RealmOrigin;
+  use std::time::Duration;
+
+  const TTL: Duration = Duration::from_secs(10);
+  const EVICTION_DELAY: Duration = Duration::from_secs(20);
+
+  fn service(display: StaleRealmDisplay) -> RealmRpc {
+    let expiry = RealmExpiry { ttl: TTL, eviction_delay: EVICTION_DELAY, display };
+    RealmRpc::new(
+      RealmServerList::new(),
+      StaticRealmPolicy::Reject,
+      Some(expiry),
+      CloseSignal::detached(),
+    )
+  }
+
+  /// Registers a realm, returning its session's realm ID and eviction signal.
+  fn register(
+    service: &RealmRpc,
+    id: RealmServerId,
+  ) -> (Arc<AtomicUsize>, oneshot::Receiver<()>) {
+    service
+      .realms
+      .add(RealmServer {
+        id,
+        host: "127.0.0.1".into(),
+        port: 55901,
+        clients: 5,
+        capacity: 100,
+        origin: RealmOrigin::Rpc,
+        state: RealmState::Online,
+      }).unwrap();
+
+    let realm_id = Arc::new(AtomicUsize::new(id as usize));
+    let (evict_tx, evict_rx) = oneshot::channel();
+    service.track_realm(id, realm_id.clone(), evict_tx);
+    (realm_id, evict_rx)
+  }
+
+  /// Backdates the last status update of a realm.
+  fn age(service: &RealmRpc, id: RealmServerId, elapsed: Duration) {
+    service.heartbeats.lock().get_mut(&id).unwrap().updated = Instant::now() - elapsed;
+  }
+
+  fn status() -> proto::RealmParams_RealmStatus {
+    let mut status = proto::RealmParams_RealmStatus::new();
+    status.set_clients(7);
+    status.set_capacity(100);
+    status
+  }
+
+  #[test]
+  fn keeps_recently_updated_realms() {
+    let service = service(StaleRealmDisplay::Hidden);
+    let (_realm_id, mut evict_rx) = register(&service, 0);
+    age(&service, 0, TTL / 2);
+    service.expire_realms();
+
+    let realm = service.realms.get(0).unwrap();
+    assert_eq!((realm.clients, realm.state), (5, RealmState::Online));
+    assert!(!service.heartbeats.lock()[&0].stale);
+    assert_eq!(evict_rx.try_recv(), Ok(None));
+  }
+
+  #[test]
+  fn lists_stale_realms_as_full() {
+    let service = service(StaleRealmDisplay::Full);
+    let _session = register(&service, 0);
+    age(&service, 0, TTL);
+    service.expire_realms();
+
+    let realm = service.realms.get(0).unwrap();
+    assert_eq!((realm.clients, realm.state), (100, RealmState::Online));
+    assert!(service.heartbeats.lock()[&0].stale);
+  }
+
+  #[test]
+  fn hides_stale_realms() {
+    let service = service(StaleRealmDisplay::Hidden);
+    let _session = register(&service, 0);
+    age(&service, 0, TTL);
+    service.expire_realms();
+
+    let realm = service.realms.get(0).unwrap();
+    assert_eq!((realm.clients, realm.state), (5, RealmState::Hidden));
+    assert_eq!(service.heartbeats.lock()[&0].hidden_from, Some(RealmState::Online));
+  }
+
+  #[test]
+  fn restores_stale_realms_on_update() {
+    let service = service(StaleRealmDisplay::Hidden);
+    let _session = register(&service, 0);
+    age(&service, 0, TTL);
+    service.expire_realms();
+    service.update_realm(0, &status()).unwrap();
+
+    let realm = service.realms.get(0).unwrap();
+    assert_eq!((realm.clients, realm.state), (7, RealmState::Online));
+    let heartbeats = service.heartbeats.lock();
+    assert!(!heartbeats[&0].stale);
+    assert_eq!(heartbeats[&0].hidden_from, None);
+  }
+
+  #[test]
+  fn keeps_states_changed_whilst_stale() {
+    let service = service(StaleRealmDisplay::Hidden);
+    let _session = register(&service, 0);
+    age(&service, 0, TTL);
+    service.expire_realms();
+    service.realms.set_state(0, RealmState::Maintenance).unwrap();
+    service.update_realm(0, &status()).unwrap();
+
+    assert_eq!(service.realms.get(0).unwrap().state, RealmState::Maintenance);
+  }
+
+  #[test]
+  fn evicts_stale_realms_after_delay() {
+    let service = service(StaleRealmDisplay::Full);
+    let (realm_id, mut evict_rx) = register(&service, 0);
+    let (_other_id, mut other_rx) = register(&service, 1);
+    age(&service, 0, TTL);
+    service.expire_realms();
+    age(&service, 0, TTL + EVICTION_DELAY);
+    service.expire_realms();
+
+    assert!(service.realms.get(0).is_err());
+    assert!(!service.heartbeats.lock().contains_key(&0));
+    assert_eq!(realm_id.load(Ordering::Relaxed), UNREGISTERED);
+    assert_eq!(evict_rx.try_recv(), Ok(Some(())));
+
+    assert!(service.realms.get(1).is_ok());
+    assert_eq!(other_rx.try_recv(), Ok(None));
+  }
+
+  #[test]
+  fn skips_eviction_of_deregistering_realms() {
+    let service = service(StaleRealmDisplay::Full);
+    let (realm_id, mut evict_rx) = register(&service, 0);
+    // The session has claimed the realm for deregistering, but not removed it yet
+    realm_id.store(UNREGISTERED, Ordering::Relaxed);
+    age(&service, 0, TTL + EVICTION_DELAY);
+    service.expire_realms();
+
+    assert!(service.realms.get(0).is_ok());
+    assert!(!service.heartbeats.lock().contains_key(&0));
+    assert_eq!(evict_rx.try_recv(), Ok(None));
+  }
+
+  #[test]
+  fn untracks_deregistered_realms() {
+    let service = service(StaleRealmDisplay::Full);
+    let (realm_id, _evict_rx) = register(&service, 0);
+    service.remove_realm(0, &realm_id).unwrap();
+
+    assert!(service.realms.get(0).is_err());
+    assert!(!service.heartbeats.lock().contains_key(&0));
+  }
+
+  #[test]
+  fn keeps_heartbeats_of_later_registrations() {
+    let service = service(StaleRealmDisplay::Full);
+    let (evicted_id, _evict_rx) = register(&service, 0);
+    service.realms.remove(0).unwrap();
+    service.heartbeats.lock().remove(&0);
+    let (realm_id, _evict_rx) = register(&service, 0);
+
+    // A lingering session of the evicted registration must not untrack the current one
+    let _ = service.remove_realm(0, &evicted_id);
+    assert!(Arc::ptr_eq(&service.heartbeats.lock()[&0].realm_id, &realm_id));
+  }
+}
@@ -0,0 +1,184 @@
+use failure::Fail;
+use futures::sync::{mpsc, oneshot};
+use futures::{Future, Poll};
+use parking_lot::{Mutex, RwLock};
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{fmt, sync::Arc};
+
+/// A packet kind (e.g `0xC1`) and code.
+pub type PacketCode = (u8, u8);
+
+#[derive(Fail, Debug)]
+pub enum ExtensionListError {
+  #[fail(display = "Packet {:02X} {:02X} is already handled by '{}'", _0, _1, _2)]
+  DuplicateCode(u8, u8, String),
+
+  #[fail(display = "Packet {:02X} {:02X} is already handled by the connect server", _0, _1)]
+  ReservedCode(u8, u8),
+
+  #[fail(display = "No packet codes specified")]
+  NoCodes,
+}
+
+/// A client packet forwarded to an extension service.
+#[derive(Debug, Clone)]
+pub struct ForwardedPacket {
+  pub id: usize,
+  pub client: SocketAddr,
+  pub bytes: Vec<u8>,
+}
+
+/// An extension service registered over RPC, handling client packets of particular codes.
+pub struct Extension {
+  pub name: String,
+  pub codes: Vec<PacketCode>,
+  requests: mpsc::UnboundedSender<ForwardedPacket>,
+  pending: Mutex<HashMap<usize, oneshot::Sender<Vec<u8>>>>,
+  next_id: AtomicUsize,
+}
+
+impl Extension {
+  /// Forwards a client packet, resolving to the raw response (empty if unanswered).
+  ///
+  /// The response is canceled if the extension deregisters, and abandoned if the returned future
+  /// is dropped (e.g after a timeout).
+  pub fn forward(extension: &Arc<Self>, client: SocketAddr, bytes: Vec<u8>) -> PendingResponse {
+    let id = extension.next_id.fetch_add(1, Ordering::Relaxed);
+    let (sender, response) = oneshot::channel();
+    extension.pending.lock().insert(id, sender);
+
+    let packet = ForwardedPacket { id, client, bytes };
+    if extension.requests.unbounded_send(packet).is_err() {
+      // The extension has deregistered, canceling the response
+      extension.pending.lock().remove(&id);
+    }
+
+    PendingResponse {
+      extension: extension.clone(),
+      id,
+      response,
+    }
+  }
+
+  /// Completes a forwarded packet, returning whether it was still awaiting a response.
+  pub fn respond(&self, id: usize, bytes: Vec<u8>) -> bool {
+    self
+      .pending
+      .lock()
+      .remove(&id)
+      .map_or(false, |sender| sender.send(bytes).is_ok())
+  }
+}
+
+impl fmt::Display for Extension {
+  fn fmt(&self, output: &mut fmt::Formatter) -> fmt::Result {
+    write!(output, "{} [", self.name)?;
+    for (index, &(kind, code)) in self.codes.iter().enumerate() {
+      let separator = if index == 0 { "" } else { ", " };
+      write!(output, "{}{:02X} {:02X}", separator, kind, code)?;
+    }
+    write!(output, "]")
+  }
+}
+
+/// A response awaited from an extension service.
+pub struct PendingResponse {
+  extension: Arc<Extension>,
+  id: usize,
+  response: oneshot::Receiver<Vec<u8>>,
+}
+
+impl Future for PendingResponse {
+  type Item = Vec<u8>;
+  type Error = oneshot::Canceled;
+
+  fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+    self.response.poll()
+  }
+}
+
+impl Drop for PendingResponse {
+  fn drop(&mut self) {
+    self.extension.pending.lock().remove(&self.id);
+  }
+}
+
+/// The extension services currently registered, by the packets they handle.
+#[derive(Clone)]
+pub struct ExtensionList {
+  routes: Arc<RwLock<HashMap<PacketCode, Arc<Extension>>>>,
+  reserved: Arc<RwLock<HashSet<PacketCode>>>,
+}
+
+impl ExtensionList {
+  pub fn new() -> Self {
+    ExtensionList {
+      routes: Arc::new(RwLock::new(HashMap::new())),
+      reserved: Arc::new(RwLock::new(HashSet::new())),
+    }
+  }
+
+  /// Reserves packet codes answered by the connect server itself, refusing any extensions
+  /// registering them.
+  pub fn reserve(&self, codes: impl IntoIterator<Item = PacketCode>) {
+    self.reserved.write().extend(codes);
+  }
+
+  /// Registers an extension, returning it along with the client packets forwarded to it.
+  ///
+  /// Each packet code may only be handled by a single extension, and never one reserved by the
+  /// connect server.
+  pub fn add(
+    &self,
+    name: String,
+    codes: Vec<PacketCode>,
+  ) -> Result<(Arc<Extension>, mpsc::UnboundedReceiver<ForwardedPacket>), ExtensionListError> {
+    if codes.is_empty() {
+      Err(ExtensionListError::NoCodes)?;
+    }
+
+    let reserved = self.reserved.read();
+    if let Some(&(kind, code)) = codes.iter().find(|code| reserved.contains(code)) {
+      Err(ExtensionListError::ReservedCode(kind, code))?;
+    }
+
+    let mut routes = self.routes.write();
+    if let Some((&(kind, code), current)) = codes
+      .iter()
+      .filter_map(|code| routes.get(code).map(|current| (code, current)))
+      .next()
+    {
+      Err(ExtensionListError::DuplicateCode(kind, code, current.name.clone()))?;
+    }
+
+    let (requests, receiver) = mpsc::unbounded();
+    let extension = Arc::new(Extension {
+      name,
+      codes,
+      requests,
+      pending: Mutex::new(HashMap::new()),
+      next_id: AtomicUsize::new(0),
+    });
+
+    for &code in &extension.codes {
+      routes.insert(code, extension.clone());
+    }
+    Ok((extension, receiver))
+  }
+
+  /// Removes an extension, canceling any packets awaiting its response.
+  pub fn remove(&self, extension: &Arc<Extension>) {
+    self
+      .routes
+      .write()
+      .retain(|_, current| !Arc::ptr_eq(current, extension));
+    extension.pending.lock().clear();
+  }
+
+  /// Returns the extension handling a packet code, if any.
+  pub fn route(&self, code: PacketCode) -> Option<Arc<Extension>> {
+    self.routes.read().get(&code).cloned()
+  }
+}
@@ -1,3 +1,5 @@
+pub use self::extension::*;
 pub use self::realm::*;
 
+mod extension;
 mod realm;
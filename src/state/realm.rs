@@ -1,10 +1,173 @@
 use chashmap::CHashMap;
-use failure::Fail;
-use std::{cell::RefCell, fmt, sync::Arc};
+use failure::{format_err, Error, Fail};
+use parking_lot::{Mutex, RwLock};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::{cell::RefCell, fmt, str::FromStr, sync::Arc};
 
 /// A realm server identifier.
 pub type RealmServerId = u16;
 
+/// A realm group identifier.
+pub type RealmGroupId = u16;
+
+/// The number of consecutive realm IDs the client displays within each group.
+pub const REALM_GROUP_SIZE: RealmServerId = 20;
+
+/// The origin of a realm server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RealmOrigin {
+  /// Declared in the configuration.
+  Static,
+  /// Registered over RPC.
+  Rpc,
+}
+
+/// Policy for RPC registrations conflicting with a static realm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StaticRealmPolicy {
+  /// The registration is refused.
+  Reject,
+  /// The registration replaces the static realm until it deregisters.
+  Override,
+}
+
+impl FromStr for StaticRealmPolicy {
+  type Err = Error;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "reject" => Ok(StaticRealmPolicy::Reject),
+      "override" => Ok(StaticRealmPolicy::Override),
+      _ => Err(format_err!("Expected 'reject' or 'override'")),
+    }
+  }
+}
+
+/// The availability of a realm server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RealmState {
+  /// Listed and joinable by all clients.
+  Online,
+  /// Listed as full or omitted, and only joinable by staff clients.
+  Maintenance,
+  /// Never listed, and only joinable by staff clients.
+  Hidden,
+  /// Only listed and joinable by staff clients.
+  StaffOnly,
+}
+
+impl RealmState {
+  /// Returns whether a client may join the realm.
+  pub fn is_joinable(self, staff: bool) -> bool {
+    self == RealmState::Online || staff
+  }
+}
+
+impl Default for RealmState {
+  fn default() -> Self {
+    RealmState::Online
+  }
+}
+
+impl FromStr for RealmState {
+  type Err = Error;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "online" => Ok(RealmState::Online),
+      "maintenance" => Ok(RealmState::Maintenance),
+      "hidden" => Ok(RealmState::Hidden),
+      "staffOnly" => Ok(RealmState::StaffOnly),
+      _ => Err(format_err!(
+        "Expected 'online', 'maintenance', 'hidden' or 'staffOnly'"
+      )),
+    }
+  }
+}
+
+impl fmt::Display for RealmState {
+  fn fmt(&self, output: &mut fmt::Formatter) -> fmt::Result {
+    output.write_str(match self {
+      RealmState::Online => "online",
+      RealmState::Maintenance => "maintenance",
+      RealmState::Hidden => "hidden",
+      RealmState::StaffOnly => "staffOnly",
+    })
+  }
+}
+
+/// How realms under maintenance are listed to clients who are not staff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MaintenanceDisplay {
+  /// The realm is listed at full capacity.
+  Full,
+  /// The realm is left out of the list.
+  Omit,
+}
+
+impl FromStr for MaintenanceDisplay {
+  type Err = Error;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "full" => Ok(MaintenanceDisplay::Full),
+      "omit" => Ok(MaintenanceDisplay::Omit),
+      _ => Err(format_err!("Expected 'full' or 'omit'")),
+    }
+  }
+}
+
+/// How realms without recent status updates are listed until they are evicted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StaleRealmDisplay {
+  /// The realm is listed at full capacity.
+  Full,
+  /// The realm is hidden.
+  Hidden,
+}
+
+impl FromStr for StaleRealmDisplay {
+  type Err = Error;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "full" => Ok(StaleRealmDisplay::Full),
+      "hidden" => Ok(StaleRealmDisplay::Hidden),
+      _ => Err(format_err!("Expected 'full' or 'hidden'")),
+    }
+  }
+}
+
+/// How clients joining a full realm are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RealmOverflow {
+  /// The client is sent to the realm regardless.
+  Allow,
+  /// The request is refused.
+  Reject,
+  /// The client is sent to the least loaded realm of the same group, if any has room.
+  Redirect,
+}
+
+impl FromStr for RealmOverflow {
+  type Err = Error;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "allow" => Ok(RealmOverflow::Allow),
+      "reject" => Ok(RealmOverflow::Reject),
+      "redirect" => Ok(RealmOverflow::Redirect),
+      _ => Err(format_err!("Expected 'allow', 'reject' or 'redirect'")),
+    }
+  }
+}
+
 /// Realm server information.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct RealmServer {
@@ -13,9 +176,21 @@ pub struct RealmServer {
   pub port: u16,
   pub clients: usize,
   pub capacity: usize,
+  pub origin: RealmOrigin,
+  pub state: RealmState,
 }
 
 impl RealmServer {
+  /// Returns the group the realm is displayed within.
+  pub fn group(&self) -> RealmGroupId {
+    self.id / REALM_GROUP_SIZE
+  }
+
+  /// Returns whether the realm is full, allowing it to overcommit by a margin.
+  pub fn is_full(&self, margin: usize) -> bool {
+    self.clients >= self.capacity.saturating_add(margin)
+  }
+
   pub fn load_factor(&self) -> f32 {
     self.clients as f32 / self.capacity as f32
   }
@@ -27,7 +202,38 @@ impl fmt::Display for RealmServer {
       output,
       "{}:{} <{}> [{}/{}]",
       &self.host, self.port, self.id, self.clients, self.capacity
-    )
+    )?;
+    match self.state {
+      RealmState::Online => Ok(()),
+      state => write!(output, " ({})", state),
+    }
+  }
+}
+
+/// Display metadata of a realm group.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RealmGroup {
+  pub id: RealmGroupId,
+  pub name: String,
+  pub order: u32,
+  pub visible: bool,
+}
+
+impl RealmGroup {
+  /// Constructs the metadata of an undeclared group, displayed in ID order.
+  pub fn new(id: RealmGroupId) -> Self {
+    RealmGroup {
+      id,
+      name: format!("Group {}", id),
+      order: u32::from(id),
+      visible: true,
+    }
+  }
+}
+
+impl fmt::Display for RealmGroup {
+  fn fmt(&self, output: &mut fmt::Formatter) -> fmt::Result {
+    write!(output, "{} <{}>", &self.name, self.id)
   }
 }
 
@@ -38,34 +244,254 @@ pub enum RealmServerListError {
 
   #[fail(display = "Inexistent realm ID")]
   InexistentId,
+
+  #[fail(display = "Realm ID reserved by a static realm")]
+  StaticId,
+
+  #[fail(display = "Realm unavailable ({})", _0)]
+  Unavailable(RealmState),
+
+  #[fail(display = "Realm is full")]
+  Full,
 }
 
 #[derive(Clone)]
 pub struct RealmServerList {
   realms: Arc<CHashMap<RealmServerId, RealmServer>>,
+  statics: Arc<Mutex<HashMap<RealmServerId, RealmServer>>>,
+  groups: Arc<RwLock<HashMap<RealmGroupId, RealmGroup>>>,
+  maintenance: Arc<RwLock<MaintenanceDisplay>>,
+  overflow: Arc<RwLock<(RealmOverflow, usize)>>,
 }
 
 impl RealmServerList {
   pub fn new() -> Self {
     RealmServerList {
       realms: Arc::new(CHashMap::new()),
+      statics: Arc::new(Mutex::new(HashMap::new())),
+      groups: Arc::new(RwLock::new(HashMap::new())),
+      maintenance: Arc::new(RwLock::new(MaintenanceDisplay::Full)),
+      overflow: Arc::new(RwLock::new((RealmOverflow::Allow, 0))),
     }
   }
 
   pub fn add(&self, realm: RealmServer) -> Result<(), RealmServerListError> {
-    if self.realms.contains_key(&realm.id) {
-      Err(RealmServerListError::DuplicateId)?;
+    if let Some(current) = self.realms.get(&realm.id) {
+      Err(match current.origin {
+        RealmOrigin::Static => RealmServerListError::StaticId,
+        RealmOrigin::Rpc => RealmServerListError::DuplicateId,
+      })?;
     }
 
     self.realms.insert_new(realm.id, realm);
     Ok(())
   }
 
+  /// Adds a realm, replacing any static realm with the same ID.
+  pub fn add_override(&self, realm: RealmServer) -> Result<(), RealmServerListError> {
+    let mut result = Ok(());
+    self.realms.alter(realm.id, |current| match current {
+      Some(ref current) if current.origin != RealmOrigin::Static => {
+        result = Err(RealmServerListError::DuplicateId);
+        Some(current.clone())
+      }
+      _ => Some(realm),
+    });
+    result
+  }
+
+  /// Removes a realm, restoring its static definition if there is one.
   pub fn remove(&self, id: RealmServerId) -> Result<RealmServer, RealmServerListError> {
-    self
+    let realm = self
       .realms
       .remove(&id)
-      .ok_or(RealmServerListError::InexistentId)
+      .ok_or(RealmServerListError::InexistentId)?;
+
+    if let Some(definition) = self.statics.lock().get(&id) {
+      self.realms.insert_new(id, definition.clone());
+    }
+    Ok(realm)
+  }
+
+  /// Replaces all static realm definitions.
+  ///
+  /// Static realms which are no longer defined are removed, whilst any realms registered over
+  /// RPC are left untouched. The current state of a static realm is kept, unless the state of its
+  /// definition has changed.
+  pub fn set_static(&self, realms: Vec<RealmServer>) {
+    let mut statics = self.statics.lock();
+    let definitions = realms
+      .into_iter()
+      .map(|realm| (realm.id, realm))
+      .collect::<HashMap<_, _>>();
+
+    for id in statics.keys().filter(|id| !definitions.contains_key(id)) {
+      self.realms.alter(*id, |current| {
+        current.filter(|realm| realm.origin != RealmOrigin::Static)
+      });
+    }
+
+    for (id, definition) in &definitions {
+      let is_state_unchanged = statics
+        .get(id)
+        .map_or(false, |previous| previous.state == definition.state);
+      self.realms.alter(*id, |current| match current {
+        Some(ref current) if current.origin != RealmOrigin::Static => Some(current.clone()),
+        Some(ref current) if is_state_unchanged => Some(RealmServer {
+          state: current.state,
+          ..definition.clone()
+        }),
+        _ => Some(definition.clone()),
+      });
+    }
+
+    *statics = definitions;
+  }
+
+  /// Replaces all realm group definitions.
+  pub fn set_groups(&self, groups: Vec<RealmGroup>) {
+    *self.groups.write() = groups.into_iter().map(|group| (group.id, group)).collect();
+  }
+
+  /// Returns the metadata of a realm group, whether declared or not.
+  pub fn group(&self, id: RealmGroupId) -> RealmGroup {
+    self
+      .groups
+      .read()
+      .get(&id)
+      .cloned()
+      .unwrap_or_else(|| RealmGroup::new(id))
+  }
+
+  /// Changes how realms under maintenance are listed.
+  pub fn set_maintenance_display(&self, display: MaintenanceDisplay) {
+    *self.maintenance.write() = display;
+  }
+
+  /// Changes how clients joining a full realm are handled, and by how many clients VIP clients
+  /// may overcommit a realm.
+  pub fn set_overflow(&self, overflow: RealmOverflow, vip_margin: usize) {
+    *self.overflow.write() = (overflow, vip_margin);
+  }
+
+  /// Changes the state of a realm, until it re-registers or the state of its static definition
+  /// changes.
+  pub fn set_state(
+    &self,
+    id: RealmServerId,
+    state: RealmState,
+  ) -> Result<RealmServer, RealmServerListError> {
+    let mut realm = self.get_mut(id)?;
+    realm.state = state;
+    Ok(realm.clone())
+  }
+
+  /// Returns the realm a client joins when requesting a realm.
+  ///
+  /// This is the requested realm, unless it is full and clients are redirected elsewhere. Clients
+  /// are only redirected to realms listed to them, including only those accepted by `visible`,
+  /// and VIP clients may overcommit realms by the overflow margin.
+  pub fn joinable(
+    &self,
+    id: RealmServerId,
+    staff: bool,
+    vip: bool,
+    visible: impl Fn(&RealmServer) -> bool,
+  ) -> Result<RealmServer, RealmServerListError> {
+    // The entry must be released before iterating the realms
+    let realm = self.get(id)?.clone();
+    if !realm.state.is_joinable(staff) {
+      Err(RealmServerListError::Unavailable(realm.state))?;
+    }
+
+    let (overflow, vip_margin) = *self.overflow.read();
+    let margin = if vip { vip_margin } else { 0 };
+    match overflow {
+      _ if !realm.is_full(margin) => Ok(realm),
+      RealmOverflow::Allow => Ok(realm),
+      RealmOverflow::Reject => Err(RealmServerListError::Full),
+      RealmOverflow::Redirect => self
+        .least_loaded(realm.group(), staff, margin, visible)
+        .ok_or(RealmServerListError::Full),
+    }
+  }
+
+  /// Returns the least loaded realm of a group with room for a client, preferring lower IDs.
+  ///
+  /// Only realms listed as is to the client are considered, i.e within a visible group, joinable,
+  /// never hidden and accepted by `visible`.
+  fn least_loaded(
+    &self,
+    group: RealmGroupId,
+    staff: bool,
+    margin: usize,
+    visible: impl Fn(&RealmServer) -> bool,
+  ) -> Option<RealmServer> {
+    let group_visible = self.groups.read().get(&group).map_or(true, |group| group.visible);
+    if !group_visible {
+      return None;
+    }
+
+    // Candidates are collected first, so filters aren't evaluated whilst iterating the realms
+    let mut candidates = Vec::new();
+    self.for_each(|realm| {
+      let listed = match realm.state {
+        RealmState::Online => true,
+        RealmState::Maintenance | RealmState::StaffOnly => staff,
+        RealmState::Hidden => false,
+      };
+      if realm.group() == group && listed && !realm.is_full(margin) {
+        candidates.push(realm.clone());
+      }
+    });
+
+    let mut least: Option<RealmServer> = None;
+    for realm in candidates.into_iter().filter(|realm| visible(realm)) {
+      // Compare the load factors without dividing, since the capacity may be zero
+      let is_less = least.as_ref().map_or(true, |least| {
+        let load = realm.clients as u64 * least.capacity as u64;
+        let least_load = least.clients as u64 * realm.capacity as u64;
+        load < least_load || (load == least_load && realm.id < least.id)
+      });
+      if is_less {
+        least = Some(realm);
+      }
+    }
+    least
+  }
+
+  /// Returns the realms of all visible groups listed to a client, in display order.
+  ///
+  /// Groups are sorted by their order followed by their ID, and realms within a group by their
+  /// ID, so the listing is the same regardless of registration order. Hidden realms are never
+  /// listed, whilst realms under maintenance are listed as is to staff clients.
+  pub fn listing(&self, staff: bool) -> Vec<RealmServer> {
+    let groups = self.groups.read();
+    let group = |id: RealmGroupId| groups.get(&id).map(|group| (group.order, group.visible));
+    let maintenance = *self.maintenance.read();
+
+    let mut list = Vec::with_capacity(self.len());
+    self.for_each(|realm| {
+      let (order, visible) = group(realm.group()).unwrap_or((u32::from(realm.group()), true));
+      let realm = match (realm.state, staff, maintenance) {
+        _ if !visible => None,
+        (RealmState::Online, _, _) => Some(realm.clone()),
+        (RealmState::Hidden, _, _) => None,
+        (RealmState::StaffOnly, staff, _) => Some(realm.clone()).filter(|_| staff),
+        (RealmState::Maintenance, true, _) => Some(realm.clone()),
+        (RealmState::Maintenance, false, MaintenanceDisplay::Omit) => None,
+        (RealmState::Maintenance, false, MaintenanceDisplay::Full) => Some(RealmServer {
+          clients: realm.capacity,
+          ..realm.clone()
+        }),
+      };
+      if let Some(realm) = realm {
+        list.push((order, realm));
+      }
+    });
+
+    list.sort_by_key(|&(order, ref realm)| (order, realm.group(), realm.id));
+    list.into_iter().map(|(_, realm)| realm).collect()
   }
 
   pub fn for_each<F: FnMut(&RealmServer)>(&self, func: F) {
@@ -100,3 +526,175 @@ impl RealmServerList {
     self.realms.len()
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn realm(id: RealmServerId, clients: usize, capacity: usize) -> RealmServer {
+    RealmServer {
+      id,
+      host: "127.0.0.1".into(),
+      port: 55901,
+      clients,
+      capacity,
+      origin: RealmOrigin::Static,
+      state: RealmState::Online,
+    }
+  }
+
+  fn list(overflow: RealmOverflow, margin: usize, realms: Vec<RealmServer>) -> RealmServerList {
+    let list = RealmServerList::new();
+    list.set_overflow(overflow, margin);
+    for realm in realms {
+      list.add(realm).unwrap();
+    }
+    list
+  }
+
+  /// Returns the ID of the realm a client joins, if any.
+  fn join(
+    list: &RealmServerList,
+    id: RealmServerId,
+    staff: bool,
+    vip: bool,
+  ) -> Option<RealmServerId> {
+    list.joinable(id, staff, vip, |_| true).ok().map(|realm| realm.id)
+  }
+
+  #[test]
+  fn joins_realms_with_room() {
+    let list = list(RealmOverflow::Reject, 0, vec![realm(0, 99, 100)]);
+    assert_eq!(join(&list, 0, false, false), Some(0));
+    assert!(matches!(
+      list.joinable(1, false, false, |_| true),
+      Err(RealmServerListError::InexistentId)
+    ));
+  }
+
+  #[test]
+  fn applies_overflow_policy_to_full_realms() {
+    let full = || vec![realm(0, 100, 100), realm(1, 0, 100)];
+    assert_eq!(join(&list(RealmOverflow::Allow, 0, full()), 0, false, false), Some(0));
+    assert!(matches!(
+      list(RealmOverflow::Reject, 0, full()).joinable(0, false, false, |_| true),
+      Err(RealmServerListError::Full)
+    ));
+    assert_eq!(join(&list(RealmOverflow::Redirect, 0, full()), 0, false, false), Some(1));
+  }
+
+  #[test]
+  fn applies_margin_to_vip_clients_only() {
+    let list = list(RealmOverflow::Reject, 5, vec![realm(0, 104, 100)]);
+    assert_eq!(join(&list, 0, false, true), Some(0));
+    assert_eq!(join(&list, 0, false, false), None);
+    assert_eq!(join(&list, 0, true, false), None);
+  }
+
+  #[test]
+  fn refuses_unavailable_realms_unless_staff() {
+    let list = list(RealmOverflow::Allow, 0, vec![realm(0, 0, 100)]);
+    for &state in &[RealmState::Maintenance, RealmState::Hidden, RealmState::StaffOnly] {
+      list.set_state(0, state).unwrap();
+      assert!(matches!(
+        list.joinable(0, false, false, |_| true),
+        Err(RealmServerListError::Unavailable(_))
+      ));
+      assert_eq!(join(&list, 0, true, false), Some(0));
+    }
+  }
+
+  #[test]
+  fn redirects_to_least_loaded_realm_of_group() {
+    let list = list(
+      RealmOverflow::Redirect,
+      0,
+      vec![
+        realm(0, 100, 100),
+        realm(1, 60, 100),
+        realm(2, 30, 100),
+        realm(3, 15, 50),
+        realm(4, 100, 100),
+        realm(20, 0, 100),
+      ],
+    );
+    // Realms 2 and 3 are equally loaded, so the lower ID is preferred
+    assert_eq!(join(&list, 0, false, false), Some(2));
+    assert_eq!(
+      list.joinable(0, false, false, |realm| realm.id != 2).unwrap().id,
+      3
+    );
+    assert_eq!(
+      list.joinable(0, false, false, |realm| realm.id == 0).ok(),
+      None
+    );
+  }
+
+  #[test]
+  fn redirects_only_to_listed_realms() {
+    let list = list(
+      RealmOverflow::Redirect,
+      0,
+      vec![realm(0, 100, 100), realm(1, 0, 100), realm(2, 10, 100)],
+    );
+    list.set_state(1, RealmState::Hidden).unwrap();
+    assert_eq!(join(&list, 0, false, false), Some(2));
+    assert_eq!(join(&list, 0, true, false), Some(2));
+
+    list.set_state(1, RealmState::Maintenance).unwrap();
+    list.set_state(2, RealmState::StaffOnly).unwrap();
+    assert_eq!(join(&list, 0, false, false), None);
+    assert_eq!(join(&list, 0, true, false), Some(1));
+  }
+
+  #[test]
+  fn redirects_nowhere_within_hidden_groups() {
+    let list = list(
+      RealmOverflow::Redirect,
+      0,
+      vec![realm(0, 100, 100), realm(1, 0, 100)],
+    );
+    list.set_groups(vec![RealmGroup {
+      visible: false,
+      ..RealmGroup::new(0)
+    }]);
+    assert_eq!(join(&list, 0, false, false), None);
+  }
+
+  #[test]
+  fn treats_realms_without_capacity_as_most_loaded() {
+    let list = list(
+      RealmOverflow::Redirect,
+      10,
+      vec![realm(0, 110, 100), realm(1, 2, 0), realm(2, 50, 100)],
+    );
+    assert_eq!(join(&list, 0, false, true), Some(2));
+    assert_eq!(join(&list, 0, false, false), Some(2));
+  }
+
+  #[test]
+  fn reloading_statics_keeps_changed_states() {
+    let realms = RealmServerList::new();
+    realms.set_static(vec![realm(0, 0, 100), realm(1, 0, 100)]);
+    realms.set_state(0, RealmState::Maintenance).unwrap();
+    realms.set_state(1, RealmState::Maintenance).unwrap();
+
+    let mut changed = realm(1, 0, 200);
+    changed.state = RealmState::StaffOnly;
+    realms.set_static(vec![realm(0, 0, 200), changed]);
+
+    let unchanged = realms.get(0).unwrap().clone();
+    assert_eq!(unchanged.state, RealmState::Maintenance);
+    assert_eq!(unchanged.capacity, 200);
+    assert_eq!(realms.get(1).unwrap().state, RealmState::StaffOnly);
+  }
+
+  #[test]
+  fn reloading_statics_removes_undefined_realms() {
+    let realms = RealmServerList::new();
+    realms.set_static(vec![realm(0, 0, 100), realm(1, 0, 100)]);
+    realms.set_static(vec![realm(1, 0, 100)]);
+    assert!(realms.get(0).is_err());
+    assert!(realms.get(1).is_ok());
+  }
+}
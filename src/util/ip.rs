@@ -0,0 +1,64 @@
+use failure::{format_err, Error};
+use ipnet::IpNet;
+use serde::{de, Deserialize, Deserializer};
+use std::net::IpAddr;
+use std::{fmt, str::FromStr};
+
+/// An IP address or CIDR range (e.g `10.0.0.1` or `10.0.0.0/8`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpNetwork(pub IpNet);
+
+impl IpNetwork {
+  /// Returns whether an address is within the network.
+  pub fn contains(&self, ip: IpAddr) -> bool {
+    self.0.contains(&canonical_ip(ip))
+  }
+}
+
+impl FromStr for IpNetwork {
+  type Err = Error;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    let value = value.trim();
+    value
+      .parse::<IpNet>()
+      .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+      .map(IpNetwork)
+      .map_err(|_| format_err!("Invalid IP address or CIDR range '{}'", value))
+  }
+}
+
+impl fmt::Display for IpNetwork {
+  fn fmt(&self, output: &mut fmt::Formatter) -> fmt::Result {
+    write!(output, "{}", self.0)
+  }
+}
+
+impl<'de> Deserialize<'de> for IpNetwork {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    String::deserialize(deserializer)?
+      .parse()
+      .map_err(de::Error::custom)
+  }
+}
+
+/// Returns an address with any IPv4-mapped IPv6 address converted to IPv4.
+pub fn canonical_ip(ip: IpAddr) -> IpAddr {
+  match ip {
+    IpAddr::V6(ipv6) => match ipv6.segments() {
+      [0, 0, 0, 0, 0, 0xFFFF, _, _] => ipv6.to_ipv4().map_or(ip, IpAddr::V4),
+      _ => ip,
+    },
+    IpAddr::V4(_) => ip,
+  }
+}
+
+/// Returns the network address of an IP, using the prefix length of its family.
+pub fn ip_network(ip: IpAddr, ipv4_prefix_len: u8, ipv6_prefix_len: u8) -> IpAddr {
+  let ip = canonical_ip(ip);
+  let prefix_len = match ip {
+    IpAddr::V4(_) => ipv4_prefix_len,
+    IpAddr::V6(_) => ipv6_prefix_len,
+  };
+  IpNet::new(ip, prefix_len).map_or(ip, |net| net.network())
+}
@@ -1,9 +1,13 @@
 #[macro_use]
 mod macros;
 mod event;
+mod ip;
+mod rate;
 mod stream;
 mod threadctl;
 
 pub use self::event::{EventAction, EventArgs, EventHandler, EventListener};
+pub use self::ip::{canonical_ip, ip_network, IpNetwork};
+pub use self::rate::{seconds, RateLimit, TokenBucket};
 pub use self::stream::StreamExt;
 pub use self::threadctl::{CloseSignal, ThreadController};
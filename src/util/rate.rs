@@ -0,0 +1,154 @@
+use failure::{format_err, Error};
+use serde::{de, Deserialize, Deserializer};
+use std::time::{Duration, Instant};
+use std::{fmt, str::FromStr};
+
+/// A rate limit, allowing a number of events per period (e.g `5/10s`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+  pub count: u32,
+  pub period: Duration,
+}
+
+impl RateLimit {
+  /// Returns a full token bucket enforcing the limit.
+  pub fn bucket(&self) -> TokenBucket {
+    TokenBucket::new(f64::from(self.count) / seconds(self.period), f64::from(self.count))
+  }
+}
+
+impl FromStr for RateLimit {
+  type Err = Error;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    let mut parts = value.splitn(2, '/');
+    let count = parts.next().unwrap_or_default().trim();
+    let period = parts
+      .next()
+      .ok_or_else(|| format_err!("Expected a rate such as '5/10s'"))?
+      .trim();
+
+    Ok(RateLimit {
+      count: count.parse()?,
+      period: humantime::parse_duration(period)?,
+    })
+  }
+}
+
+impl fmt::Display for RateLimit {
+  fn fmt(&self, output: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      output,
+      "{}/{}",
+      self.count,
+      humantime::format_duration(self.period)
+    )
+  }
+}
+
+impl<'de> Deserialize<'de> for RateLimit {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    String::deserialize(deserializer)?
+      .parse()
+      .map_err(de::Error::custom)
+  }
+}
+
+/// A token bucket, refilling at a constant rate up to its capacity.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+  capacity: f64,
+  rate: f64,
+  tokens: f64,
+  updated: Instant,
+}
+
+impl TokenBucket {
+  /// Constructs a full bucket, refilling `rate` tokens per second.
+  pub fn new(rate: f64, capacity: f64) -> Self {
+    TokenBucket {
+      capacity,
+      rate,
+      tokens: capacity,
+      updated: Instant::now(),
+    }
+  }
+
+  /// Takes a token from the bucket, returning whether one was available.
+  pub fn try_take(&mut self) -> bool {
+    self.refill();
+    if self.tokens >= 1.0 {
+      self.tokens -= 1.0;
+      true
+    } else {
+      false
+    }
+  }
+
+  /// Returns whether a token is available, without taking it.
+  pub fn has_token(&self) -> bool {
+    self.available() >= 1.0
+  }
+
+  /// Returns whether the bucket has been refilled to its capacity.
+  pub fn is_full(&self) -> bool {
+    self.available() >= self.capacity
+  }
+
+  /// Returns the tokens available, including those refilled since the last update.
+  fn available(&self) -> f64 {
+    (self.tokens + seconds(self.updated.elapsed()) * self.rate).min(self.capacity)
+  }
+
+  fn refill(&mut self) {
+    let now = Instant::now();
+    let elapsed = seconds(now.duration_since(self.updated));
+    self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+    self.updated = now;
+  }
+}
+
+/// Returns a duration in fractional seconds.
+pub fn seconds(duration: Duration) -> f64 {
+  duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_rates() {
+    let rate: RateLimit = "5/10s".parse().unwrap();
+    assert_eq!(rate.count, 5);
+    assert_eq!(rate.period, Duration::from_secs(10));
+
+    let rate: RateLimit = " 100 / 1m ".parse().unwrap();
+    assert_eq!(rate.count, 100);
+    assert_eq!(rate.period, Duration::from_secs(60));
+
+    let rate: RateLimit = "1/500ms".parse().unwrap();
+    assert_eq!(rate.period, Duration::from_millis(500));
+  }
+
+  #[test]
+  fn displays_parsable_rates() {
+    let rate: RateLimit = "5/1m 30s".parse().unwrap();
+    assert_eq!(rate.to_string().parse::<RateLimit>().unwrap(), rate);
+  }
+
+  #[test]
+  fn rejects_malformed_rates() {
+    for value in &["", "5", "5/", "/10s", "x/10s", "-5/10s", "5/10", "5/ten seconds", "5/10s/1s"] {
+      assert!(value.parse::<RateLimit>().is_err(), "{}", value);
+    }
+  }
+
+  #[test]
+  fn buckets_start_full() {
+    let mut bucket = "2/1h".parse::<RateLimit>().unwrap().bucket();
+    assert!(bucket.try_take());
+    assert!(bucket.try_take());
+    assert!(!bucket.try_take());
+  }
+}
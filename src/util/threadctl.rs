@@ -10,6 +10,15 @@ pub struct CloseSignal {
   receiver: Shared<oneshot::Receiver<()>>,
 }
 
+#[cfg(test)]
+impl CloseSignal {
+  /// Creates a close signal without a controller, which never fires.
+  pub fn detached() -> Self {
+    let (_, receiver) = oneshot::channel();
+    CloseSignal { receiver: receiver.shared() }
+  }
+}
+
 impl Future for CloseSignal {
   type Item = ();
   type Error = ();